@@ -0,0 +1,142 @@
+use rand::Rng;
+
+use complex::*;
+use gradient::*;
+use output::*;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DensityMode{
+    /// Orbits of points escaping within the iteration limit.
+    Buddhabrot,
+    /// Three Buddhabrots with different iteration limits mapped to red, green and blue.
+    Nebulabrot,
+    /// Orbits of points that did not escape within the iteration limit.
+    AntiBuddhabrot,
+}
+
+pub struct Buddhabrot{
+    pub mode: DensityMode,
+    pub limits: [usize; 3],
+    /// Random `c` values each worker samples per frame.
+    pub samples: usize,
+}
+
+/// Frames orbits keep being accumulated for while the view stays the same, the image hardly
+/// changes any more after that.
+pub const DENSITY_FRAMES: u32 = 200;
+
+impl Buddhabrot {
+    pub fn new(mode: DensityMode, limits: [usize; 3], samples: usize) -> Buddhabrot{
+        Buddhabrot{
+            mode: mode,
+            limits: limits,
+            samples: samples,
+        }
+    }
+
+    fn channels(&self) -> usize {
+        if self.mode == DensityMode::Nebulabrot { 3 } else { 1 }
+    }
+
+    /// Samples `samples` random `c` values and adds their orbits to `counts`, which holds three
+    /// channels per pixel of a `width` x `heigth` view.
+    pub fn accumulate<R: Rng>(&self, rng: &mut R, samples: usize, counts: &mut [u32], width: usize, heigth: usize, scale: (f64, f64), center: (f64, f64)) {
+        let channels = self.channels();
+        let max = self.limits[..channels].iter().cloned().max().unwrap_or(0);
+        let mut orbit = Vec::with_capacity(max);
+
+        for _ in 0 .. samples {
+            let c = Complex::new(rng.gen::<f64>() * 4.0 - 2.0, rng.gen::<f64>() * 4.0 - 2.0);
+            if self.mode != DensityMode::AntiBuddhabrot && in_main_bulbs(c) {
+                continue;
+            }
+
+            orbit.clear();
+            let mut z = c;
+            let mut escaped = false;
+            while orbit.len() < max {
+                orbit.push(z);
+                if z.norm_sqr() > 4.0 {
+                    escaped = true;
+                    break;
+                }
+                z = z * z + c;
+            }
+
+            for channel in 0 .. channels {
+                let limit = self.limits[channel];
+                let plot = match self.mode {
+                    DensityMode::Buddhabrot | DensityMode::Nebulabrot => escaped && orbit.len() <= limit,
+                    DensityMode::AntiBuddhabrot => !escaped,
+                };
+                if !plot {
+                    continue;
+                }
+
+                for z in orbit.iter().take(limit) {
+                    let x = ((z.re + center.0) / scale.0 + 1.0) / 2.0 * width as f64;
+                    let y = ((z.im + center.1) / scale.1 + 1.0) / 2.0 * heigth as f64;
+                    if x >= 0.0 && y >= 0.0 && x < width as f64 && y < heigth as f64 {
+                        counts[(y as usize * width + x as usize) * 3 + channel] += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Points inside the main cardioid and period-2 bulb never escape, so they can be skipped
+/// without iterating.
+fn in_main_bulbs(c: Complex) -> bool {
+    let q = (c.re - 0.25) * (c.re - 0.25) + c.im * c.im;
+    q * (q + c.re - 0.25) < 0.25 * c.im * c.im || (c.re + 1.0) * (c.re + 1.0) + c.im * c.im < 1.0 / 16.0
+}
+
+/// Orbit hit counts accumulated over all frames rendered with the same view.
+pub struct Density{
+    pub width: usize,
+    pub heigth: usize,
+    pub counts: Vec<u32>,
+}
+
+impl Density {
+    pub fn new(width: usize, heigth: usize) -> Density{
+        Density{
+            width: width,
+            heigth: heigth,
+            counts: vec![0; width * heigth * 3],
+        }
+    }
+
+    pub fn add(&mut self, counts: &[u32]) {
+        if counts.len() != self.counts.len() {
+            return;
+        }
+        for (total, &count) in self.counts.iter_mut().zip(counts.iter()) {
+            *total += count;
+        }
+    }
+
+    /// Colours the density into `target`, pixels in the same order as the counts.
+    pub fn write_colors(&self, mode: DensityMode, gradient: &Gradient, phase: f64, target: &mut Target, dither: &Dither) {
+        let mut max = [1u32; 3];
+        for (n, &count) in self.counts.iter().enumerate() {
+            if count > max[n % 3] {
+                max[n % 3] = count;
+            }
+        }
+
+        let span = gradient.span();
+        for (n, counts) in self.counts.chunks(3).enumerate().take(target.pixels()) {
+            let value = |channel: usize| (counts[channel] as f64 / max[channel] as f64).sqrt();
+            let color = if mode == DensityMode::Nebulabrot {
+                Some((value(0), value(1), value(2)))
+            }else if counts[0] == 0 {
+                None
+            }else{
+                Some(interpolate(gradient, value(0) * span, phase))
+            };
+            target.put(n, color, n % self.width, n / self.width, dither);
+        }
+    }
+}
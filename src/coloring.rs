@@ -0,0 +1,564 @@
+use std::cmp;
+use std::f64::consts::PI;
+
+use complex::*;
+use options::*;
+use picture::*;
+use fractal::*;
+use newton::*;
+use sample::*;
+use gradient::*;
+
+/// Colour with sRGB encoded channels from 0 to 1. Colours only get quantised when they are
+/// written out, so gradients keep their full precision until then.
+pub type Rgb = (f64, f64, f64);
+
+/// How escape-time fractals turn an orbit into a colour.
+pub enum Coloring{
+    /// Smooth (normalized) iteration count.
+    Smooth,
+    /// Minimum distance of the orbit to a trap shape.
+    OrbitTrap(Trap),
+    /// Average of a statistic over the orbit.
+    Average(Statistic),
+    /// Smooth iteration darkened by which of the given number of sectors the argument of
+    /// the final `z` falls in, 2 sectors give binary decomposition.
+    Decomposition(u32),
+    /// Smooth iteration with dark lines where the argument of the final `z` is a multiple of
+    /// `2 pi / lines`, following the external rays.
+    FieldLines(u32),
+    /// Smooth iteration mapped through its cumulative distribution over the whole frame,
+    /// so every gradient colour covers a similar share of the escaped pixels.
+    Histogram,
+}
+
+impl Coloring {
+    /// All colourings configured on the command line, the first one is used on start and
+    /// the rest can be cycled through at runtime.
+    pub fn from_options(options: &Options) -> Result<Vec<Coloring>, String> {
+        let trap = try!(Trap::from_options(options));
+        let stripes = try!(options.get_or("stripe-density", 5.0));
+        let sectors = try!(options.get_or("decomposition", 2));
+        let lines = try!(options.get_or("field-lines", 8));
+        if sectors == 0 || lines == 0 {
+            return Err("Decomposition and field lines need at least one sector".to_string());
+        }
+        let mut colorings = vec![
+            Coloring::Smooth,
+            Coloring::OrbitTrap(trap),
+            Coloring::Average(Statistic::TriangleInequality),
+            Coloring::Average(Statistic::Stripe(stripes)),
+            Coloring::Decomposition(sectors),
+            Coloring::FieldLines(lines),
+            Coloring::Histogram,
+        ];
+        let first = match options.get("coloring").unwrap_or("smooth") {
+            "smooth" => 0,
+            "trap" => 1,
+            "tia" => 2,
+            "stripe" => 3,
+            "decomposition" => 4,
+            "field-lines" => 5,
+            "histogram" => 6,
+            name => return Err(format!("Unknown coloring: {}", name)),
+        };
+        let first = colorings.remove(first);
+        colorings.insert(0, first);
+        Ok(colorings)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Coloring::Smooth => "smooth iteration",
+            Coloring::OrbitTrap(_) => "orbit trap",
+            Coloring::Average(Statistic::TriangleInequality) => "triangle inequality average",
+            Coloring::Average(Statistic::Stripe(_)) => "stripe average",
+            Coloring::Decomposition(_) => "decomposition",
+            Coloring::FieldLines(_) => "field lines",
+            Coloring::Histogram => "histogram",
+        }
+    }
+
+    /// Whether the compute pass has to observe the orbit for this colouring. Switching to or
+    /// from such a colouring needs the samples to be computed again.
+    pub fn observes_orbit(&self) -> bool {
+        match *self {
+            Coloring::OrbitTrap(_) | Coloring::Average(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Turns a sample of `fractal` into a colour, `None` is black. Only escape-time fractals
+    /// use the colouring, the others have a fixed one. `histogram` is needed for
+    /// `Coloring::Histogram`, `phase` rotates the gradients for colour cycling.
+    pub fn color(&self, fractal: &Fractal, sample: &Sample, histogram: Option<&Histogram>, gradient: &Gradient, phase: f64) -> Option<Rgb> {
+        let span = gradient.span();
+        match *fractal {
+            Fractal::Mandelbrot => {},
+            Fractal::Newton(ref newton) => return newton_color(newton, sample, gradient, phase),
+            Fractal::Lyapunov(_) => return Some(lyapunov_color(sample.stat, gradient, phase)),
+            Fractal::Buddhabrot(_) => unreachable!("Density fractals are coloured by Density"),
+        }
+
+        if let Coloring::OrbitTrap(ref trap) = *self {
+            return Some(trap.color(sample, |t| interpolate(gradient, t * span, phase)));
+        }
+        if sample.is_inside() {
+            return None;
+        }
+
+        match *self {
+            Coloring::Smooth => Some(interpolate(gradient, sample.iter, phase)),
+            Coloring::OrbitTrap(_) => unreachable!(),
+            Coloring::Average(_) => {
+                if sample.stat.is_nan() {
+                    None
+                }else{
+                    Some(interpolate(gradient, sample.stat * span, phase))
+                }
+            },
+            Coloring::Decomposition(sectors) => {
+                let sector = ((sample.z.arg() / (2.0 * PI) + 1.0).fract() * sectors as f64) as u32;
+                Some(shade(interpolate(gradient, sample.iter, phase), 1.0 - 0.65 * sector as f64 / cmp::max(sectors - 1, 1) as f64))
+            },
+            Coloring::FieldLines(lines) => {
+                // Distance to the closest of `lines` rays in the cell, 0 on a ray and 1 halfway between two.
+                let ray = (sample.z.arg() / (2.0 * PI) + 1.0).fract() * lines as f64;
+                let distance = (0.5 - (ray.fract() - 0.5).abs()) * 2.0;
+                Some(shade(interpolate(gradient, sample.iter, phase), distance.powf(0.4)))
+            },
+            Coloring::Histogram => {
+                let histogram = histogram.expect("Histogram coloring needs a histogram of the frame");
+                Some(interpolate(gradient, histogram.map(sample.iter) * span, phase))
+            },
+        }
+    }
+}
+
+/// Picks the gradient colour by the root the point converged to, spreading the roots evenly
+/// over one loop of the gradient, and darkens it the more iterations convergence took. Root
+/// indices skip the transfer function and density, which would bunch roots up or give two of
+/// them the same colour, the transfer function shapes the darkening instead.
+fn newton_color(newton: &Newton, sample: &Sample, gradient: &Gradient, phase: f64) -> Option<Rgb> {
+    if sample.is_inside() {
+        return None;
+    }
+    let color = gradient.lookup(sample.stat / newton.roots.len() as f64 + gradient.offset + phase);
+    let transfer = gradient.transfer;
+    Some(shade(color, 1.0 - (transfer.apply(sample.iter) / transfer.apply(newton.max_iter as f64)).sqrt()))
+}
+
+/// Stable regions (negative exponent) use the main gradient and chaotic ones the
+/// `CHAOS` gradient, both running from their first stop at zero towards the last. `CHAOS` is
+/// blended the way the main gradient is.
+fn lyapunov_color(exponent: f64, gradient: &Gradient, phase: f64) -> Rgb {
+    if exponent.is_nan() {
+        (0.0, 0.0, 0.0)
+    }else if exponent < 0.0 {
+        interpolate(gradient, (1.0 - exponent.exp()) * gradient.span(), phase)
+    }else{
+        let stops = (CHAOS.len() / 3 - 1) as f64;
+        interpolate_colors(&CHAOS, (1.0 - (-exponent).exp()) * stops, phase, gradient.blending())
+    }
+}
+
+fn shade(color: Rgb, k: f64) -> Rgb {
+    (color.0 * k, color.1 * k, color.2 * k)
+}
+
+/// Decodes an sRGB channel value in 0..1 to linear light.
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    }else{
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f64) -> f64 {
+    let value = value.max(0.0).min(1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    }else{
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// How colours are mixed, whether blending gradient colours, compositing layers or averaging
+/// antialiasing samples.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gamma{
+    /// In linear light, a mix of two colours is as bright as they are on average.
+    Linear,
+    /// On sRGB encoded values, which darkens mixes, to match images from older versions.
+    Legacy,
+}
+
+impl Gamma {
+    pub fn from_options(options: &Options) -> Result<Gamma, String> {
+        match options.get("gamma").unwrap_or("linear") {
+            "linear" => Ok(Gamma::Linear),
+            "legacy" => Ok(Gamma::Legacy),
+            name => Err(format!("Unknown gamma: {}", name)),
+        }
+    }
+
+    /// Turns an sRGB encoded colour into the values it is mixed as.
+    pub fn decode(self, color: Rgb) -> Rgb {
+        match self {
+            Gamma::Linear => (srgb_to_linear(color.0), srgb_to_linear(color.1), srgb_to_linear(color.2)),
+            Gamma::Legacy => color,
+        }
+    }
+
+    /// Turns a mixed colour back into an sRGB encoded one.
+    pub fn encode(self, color: Rgb) -> Rgb {
+        match self {
+            Gamma::Linear => (linear_to_srgb(color.0), linear_to_srgb(color.1), linear_to_srgb(color.2)),
+            Gamma::Legacy => color,
+        }
+    }
+}
+
+/// Nearest 8-bit value of a channel in 0..1.
+pub fn quantize(value: f64) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8
+}
+
+pub fn to_bytes(color: Rgb) -> (u8, u8, u8) {
+    (quantize(color.0), quantize(color.1), quantize(color.2))
+}
+
+pub struct Histogram{
+    /// Share of escaped pixels below each whole iteration count.
+    cdf: Vec<f64>,
+}
+
+impl Histogram {
+    /// Gathers the distribution of smooth iteration counts, values of `max` or more are
+    /// points inside the set and are left out.
+    pub fn new<I: Iterator<Item=f64>>(values: I, max: i32) -> Histogram{
+        let mut counts = vec![0usize; max as usize];
+        let mut total = 0;
+        for value in values {
+            if value < max as f64 {
+                counts[value.max(0.0) as usize] += 1;
+                total += 1;
+            }
+        }
+
+        let mut cdf = Vec::with_capacity(counts.len() + 1);
+        let mut sum = 0;
+        cdf.push(0.0);
+        for count in counts {
+            sum += count;
+            cdf.push(sum as f64 / cmp::max(total, 1) as f64);
+        }
+        Histogram{
+            cdf: cdf,
+        }
+    }
+
+    /// Position of `value` in the distribution, in 0..1.
+    pub fn map(&self, value: f64) -> f64 {
+        let value = value.max(0.0).min((self.cdf.len() - 1) as f64);
+        let n = cmp::min(value as usize, self.cdf.len() - 2);
+        let fract = value - n as f64;
+        self.cdf[n] * (1.0 - fract) + self.cdf[n + 1] * fract
+    }
+}
+
+pub enum TrapShape{
+    Point,
+    /// Line through the trap center at the trap angle.
+    Line,
+    /// Two perpendicular lines crossing at the trap center.
+    Cross,
+    /// Circle around the trap center with the trap size as radius.
+    Circle,
+    /// Picture covering a square of the trap size around the trap center. The first orbit
+    /// point that lands on an opaque part of it picks the colour.
+    Picture(Picture),
+}
+
+pub struct Trap{
+    pub shape: TrapShape,
+    pub center: Complex,
+    pub size: f64,
+    pub angle: f64,
+}
+
+impl Trap {
+    pub fn from_options(options: &Options) -> Result<Trap, String> {
+        let shape = match options.get("trap").unwrap_or("cross") {
+            "point" => TrapShape::Point,
+            "line" => TrapShape::Line,
+            "cross" => TrapShape::Cross,
+            "circle" => TrapShape::Circle,
+            path => TrapShape::Picture(try!(Picture::load(path))),
+        };
+        let center = match options.get("trap-center") {
+            Some(center) => try!(center.parse::<Complex>()),
+            None => Complex::new(0.0, 0.0),
+        };
+        Ok(Trap{
+            shape: shape,
+            center: center,
+            size: try!(options.get_or("trap-size", 0.5)),
+            angle: try!(options.get_or("trap-angle", 0.0f64)).to_radians(),
+        })
+    }
+
+    pub fn distance(&self, z: Complex) -> f64 {
+        let d = z - self.center;
+        let (sin, cos) = self.angle.sin_cos();
+        match self.shape {
+            TrapShape::Point => d.norm(),
+            TrapShape::Line => (d.re * sin - d.im * cos).abs(),
+            TrapShape::Cross => (d.re * sin - d.im * cos).abs().min((d.re * cos + d.im * sin).abs()),
+            TrapShape::Circle => (d.norm() - self.size).abs(),
+            TrapShape::Picture(_) => ::std::f64::INFINITY,
+        }
+    }
+
+    /// Passes the trap distance stored in the sample, scaled to 0..1 by the trap size, to
+    /// `gradient`, or returns the picture colour for picture traps.
+    pub fn color<F>(&self, sample: &Sample, gradient: F) -> Rgb where F: Fn(f64) -> Rgb {
+        match self.shape {
+            TrapShape::Picture(_) => (sample.hit[0] as f64 / 255.0, sample.hit[1] as f64 / 255.0, sample.hit[2] as f64 / 255.0),
+            _ => gradient(1.0 - (-sample.stat / self.size.max(1e-9)).exp()),
+        }
+    }
+
+    /// Colour of the picture trap at `z`, if it hits an opaque part of the picture.
+    pub fn sample(&self, z: Complex) -> Option<[f64; 4]> {
+        match self.shape {
+            TrapShape::Picture(ref picture) => {
+                let d = (z - self.center).scale(1.0 / self.size);
+                picture.sample(d.re + 0.5, 0.5 - d.im).and_then(|color| if color[3] > 0.0 { Some(color) } else { None })
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Gets every point of an orbit while it is being iterated.
+pub trait OrbitObserver {
+    fn visit(&mut self, z: Complex);
+
+    /// Whether `visit` does anything at all, orbit points are only converted for it if so.
+    #[inline(always)]
+    fn active(&self) -> bool {
+        true
+    }
+}
+
+impl OrbitObserver for () {
+    #[inline(always)]
+    fn visit(&mut self, _: Complex) {}
+
+    #[inline(always)]
+    fn active(&self) -> bool {
+        false
+    }
+}
+
+impl<A: OrbitObserver, B: OrbitObserver> OrbitObserver for (A, B) {
+    #[inline(always)]
+    fn visit(&mut self, z: Complex) {
+        self.0.visit(z);
+        self.1.visit(z);
+    }
+
+    #[inline(always)]
+    fn active(&self) -> bool {
+        self.0.active() || self.1.active()
+    }
+}
+
+/// Tracks the derivative of the orbit for the distance estimate.
+pub struct DistanceObserver{
+    last: Complex,
+    dz: Complex,
+}
+
+impl DistanceObserver {
+    pub fn new(c: Complex) -> DistanceObserver{
+        DistanceObserver{
+            last: c,
+            dz: Complex::new(1.0, 0.0),
+        }
+    }
+
+    pub fn estimate(&self) -> f64 {
+        let r = self.last.norm();
+        r * r.ln() / self.dz.norm()
+    }
+}
+
+impl OrbitObserver for DistanceObserver {
+    #[inline]
+    fn visit(&mut self, z: Complex) {
+        self.dz = (self.last * self.dz).scale(2.0) + Complex::new(1.0, 0.0);
+        self.last = z;
+    }
+}
+
+pub struct TrapObserver<'a>{
+    trap: &'a Trap,
+    pub distance: f64,
+    pub hit: Option<[f64; 4]>,
+}
+
+impl<'a> TrapObserver<'a> {
+    pub fn new(trap: &'a Trap) -> TrapObserver<'a>{
+        TrapObserver{
+            trap: trap,
+            distance: ::std::f64::INFINITY,
+            hit: None,
+        }
+    }
+
+    /// Picture colour the orbit hit, premultiplied by its alpha, for `Sample::hit`.
+    pub fn hit_color(&self) -> [u8; 4] {
+        match self.hit {
+            Some(color) => {
+                let alpha = color[3] / 255.0;
+                [(color[0] * alpha) as u8, (color[1] * alpha) as u8, (color[2] * alpha) as u8, color[3] as u8]
+            },
+            None => [0, 0, 0, 0],
+        }
+    }
+}
+
+impl<'a> OrbitObserver for TrapObserver<'a> {
+    #[inline]
+    fn visit(&mut self, z: Complex) {
+        if self.hit.is_none() {
+            self.hit = self.trap.sample(z);
+        }
+        let distance = self.trap.distance(z);
+        if distance < self.distance {
+            self.distance = distance;
+        }
+    }
+}
+
+pub enum Statistic{
+    /// Where `|z|` lies between the bounds the triangle inequality gives for `|z^2 + c|`.
+    TriangleInequality,
+    /// `sin(density * arg(z))` scaled to 0..1.
+    Stripe(f64),
+}
+
+/// Averages a statistic over the orbit, keeping the previous average as well so the result
+/// can be interpolated between the last two iterations like the smooth iteration count.
+pub struct AverageObserver<'a>{
+    statistic: &'a Statistic,
+    c: f64,
+    last: Complex,
+    sum: f64,
+    last_sum: f64,
+    count: usize,
+}
+
+impl<'a> AverageObserver<'a> {
+    pub fn new(statistic: &'a Statistic, c: Complex) -> AverageObserver<'a>{
+        AverageObserver{
+            statistic: statistic,
+            c: c.norm(),
+            last: c,
+            sum: 0.0,
+            last_sum: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Smoothed average in 0..1, `bailout` is the escape radius the orbit was iterated to.
+    pub fn value(&self, bailout: f64) -> Option<f64> {
+        if self.count < 2 {
+            return None;
+        }
+        let weight = (1.0 + (bailout.ln() / self.last.norm().ln()).log2()).max(0.0).min(1.0);
+        let average = self.sum / self.count as f64;
+        let last_average = self.last_sum / (self.count - 1) as f64;
+        Some(weight * average + (1.0 - weight) * last_average)
+    }
+}
+
+impl<'a> OrbitObserver for AverageObserver<'a> {
+    #[inline]
+    fn visit(&mut self, z: Complex) {
+        let value = match *self.statistic {
+            Statistic::TriangleInequality => {
+                let last = self.last.norm_sqr();
+                let low = (last - self.c).abs();
+                let high = last + self.c;
+                if high > low { (z.norm() - low) / (high - low) } else { 0.0 }
+            },
+            Statistic::Stripe(density) => 0.5 * (density * z.arg()).sin() + 0.5,
+        };
+        self.last_sum = self.sum;
+        self.sum += value;
+        self.count += 1;
+        self.last = z;
+    }
+}
+
+#[test]
+fn test_srgb(){
+    for value in 0 .. 256 {
+        assert_eq!(quantize(linear_to_srgb(srgb_to_linear(value as f64 / 255.0))), value as u8);
+    }
+    assert_eq!(srgb_to_linear(0.0), 0.0);
+    assert_eq!(srgb_to_linear(1.0), 1.0);
+    assert_eq!(to_bytes((0.0, 0.5, 2.0)), (0, 128, 255));
+    assert_eq!(Gamma::Legacy.decode((0.5, 0.5, 0.5)), (0.5, 0.5, 0.5));
+    let grey = Gamma::Linear.decode((0.5, 0.5, 0.5));
+    assert!((grey.0 - 0.214).abs() < 1e-3);
+    assert_eq!(to_bytes(Gamma::Linear.encode(grey)), (128, 128, 128));
+}
+
+#[test]
+fn test_newton_color(){
+    let newton = Newton::new(Polynomial::from_roots(&[Complex::new(1.0, 0.0), Complex::new(-1.0, 0.0)]), 64);
+    let mut gradient = Gradient::even("", &[255, 0, 0, 0, 0, 255]);
+    gradient.density = 2.0;
+    gradient.transfer = Transfer::Log;
+    gradient.update();
+    let color = |root: f64, iter: f64| newton_color(&newton, &Sample{ stat: root, iter: iter, ..Sample::default() }, &gradient, 0.0).map(to_bytes);
+    assert_eq!(color(0.0, 0.0), Some((255, 0, 0)));
+    assert_eq!(color(1.0, 0.0), Some((0, 0, 255)));
+    assert_eq!(color(1.0, 64.0), Some((0, 0, 0)));
+}
+
+#[test]
+fn test_histogram(){
+    let histogram = Histogram::new([0.5, 1.5, 1.7, 3.2, 4.0, 10.0].iter().cloned(), 4);
+    assert_eq!(histogram.map(0.0), 0.0);
+    assert_eq!(histogram.map(1.0), 0.25);
+    assert_eq!(histogram.map(1.5), 0.5);
+    assert_eq!(histogram.map(2.0), 0.75);
+    assert_eq!(histogram.map(3.0), 0.75);
+    assert_eq!(histogram.map(4.0), 1.0);
+}
+
+#[test]
+fn test_average(){
+    let stripes = Statistic::Stripe(1.0);
+    let mut observer = AverageObserver::new(&stripes, Complex::new(0.0, 0.0));
+    assert_eq!(observer.value(256.0), None);
+    observer.visit(Complex::new(1.0, 0.0));
+    observer.visit(Complex::new(-256.0, 0.0));
+    assert!((observer.value(256.0).unwrap() - 0.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_trap_distance(){
+    let trap = |shape| Trap{ shape: shape, center: Complex::new(1.0, 1.0), size: 0.5, angle: 0.0 };
+    let z = Complex::new(4.0, 5.0);
+    assert_eq!(trap(TrapShape::Point).distance(z), 5.0);
+    assert_eq!(trap(TrapShape::Line).distance(z), 4.0);
+    assert_eq!(trap(TrapShape::Cross).distance(z), 3.0);
+    assert_eq!(trap(TrapShape::Circle).distance(z), 4.5);
+}
@@ -0,0 +1,129 @@
+use std::ops::*;
+use std::str::FromStr;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Complex{
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex{
+        Complex{
+            re: re,
+            im: im,
+        }
+    }
+
+    pub fn norm_sqr(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn scale(&self, k: f64) -> Complex {
+        Complex::new(self.re * k, self.im * k)
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.re.is_finite() && self.im.is_finite()
+    }
+}
+
+impl Add for Complex{
+    type Output = Self;
+    fn add(self, other: Self) -> Self::Output{
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex{
+    type Output = Self;
+    fn sub(self, other: Self) -> Self::Output{
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex{
+    type Output = Self;
+    fn mul(self, other: Self) -> Self::Output{
+        Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+}
+
+impl Div for Complex{
+    type Output = Self;
+    fn div(self, other: Self) -> Self::Output{
+        let d = other.norm_sqr();
+        Complex::new((self.re * other.re + self.im * other.im) / d, (self.im * other.re - self.re * other.im) / d)
+    }
+}
+
+impl Neg for Complex{
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.im < 0.0 {
+            write!(f, "{}-{}i", self.re, -self.im)
+        }else{
+            write!(f, "{}+{}i", self.re, self.im)
+        }
+    }
+}
+
+/// Parses `a`, `bi`, `a+bi` and `a-bi`, e.g. `-0.5+0.866i`.
+impl FromStr for Complex {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let parse = |part: &str| part.parse::<f64>().map_err(|err| format!("Invalid complex number {}: {}", s, err));
+
+        if !s.ends_with('i') {
+            return Ok(Complex::new(try!(parse(s)), 0.0));
+        }
+
+        let body = &s[..s.len() - 1];
+        let mut split = 0;
+        let mut prev = ' ';
+        for (n, c) in body.char_indices() {
+            if n > 0 && (c == '+' || c == '-') && prev != 'e' && prev != 'E' {
+                split = n;
+            }
+            prev = c;
+        }
+
+        let (re, im) = body.split_at(split);
+        let im = match im {
+            "" | "+" => 1.0,
+            "-" => -1.0,
+            im => try!(parse(im)),
+        };
+        let re = if re.is_empty() { 0.0 } else { try!(parse(re)) };
+        Ok(Complex::new(re, im))
+    }
+}
+
+#[test]
+fn test_parse(){
+    assert_eq!("1".parse::<Complex>(), Ok(Complex::new(1.0, 0.0)));
+    assert_eq!("-2.5".parse::<Complex>(), Ok(Complex::new(-2.5, 0.0)));
+    assert_eq!("i".parse::<Complex>(), Ok(Complex::new(0.0, 1.0)));
+    assert_eq!("-i".parse::<Complex>(), Ok(Complex::new(0.0, -1.0)));
+    assert_eq!("3i".parse::<Complex>(), Ok(Complex::new(0.0, 3.0)));
+    assert_eq!("-0.5+0.866i".parse::<Complex>(), Ok(Complex::new(-0.5, 0.866)));
+    assert_eq!("-0.5-0.866i".parse::<Complex>(), Ok(Complex::new(-0.5, -0.866)));
+    assert_eq!("1e-3-2e+1i".parse::<Complex>(), Ok(Complex::new(0.001, -20.0)));
+    assert!("foo".parse::<Complex>().is_err());
+}
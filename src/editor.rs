@@ -0,0 +1,345 @@
+use std::cmp;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::Mouse;
+
+use gradient::*;
+
+const MARGIN: usize = 16;
+const GAP: usize = 4;
+const SLIDER: usize = 8;
+const BAR: usize = 24;
+const MARKER: usize = 10;
+
+/// Sliders above the gradient bar: red, green and blue of the selected stop, then the offset
+/// and density of the whole gradient.
+const SLIDERS: usize = 5;
+const OFFSET: usize = 3;
+const DENSITY: usize = 4;
+
+/// Density the density slider reaches at either end, and one over it.
+const DENSITY_RANGE: f64 = 8.0;
+
+/// How far from a marker a click still picks its stop, in pixels.
+const PICK: usize = 6;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Part{
+    Slider(usize),
+    Bar,
+    Markers,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Drag{
+    Stop,
+    Slider(usize),
+}
+
+/// Where the parts of the editor are in a window, in window coordinates with the origin in
+/// the top left corner.
+struct Layout{
+    left: usize,
+    right: usize,
+    top: usize,
+}
+
+impl Layout {
+    fn new((width, heigth): (usize, usize)) -> Option<Layout> {
+        let panel = SLIDERS * (SLIDER + GAP) + BAR + GAP + MARKER;
+        if width < MARGIN * 4 || heigth < panel + MARGIN * 2 {
+            return None;
+        }
+        Some(Layout{
+            left: MARGIN,
+            right: width - MARGIN,
+            top: heigth - MARGIN - panel,
+        })
+    }
+
+    fn slider(&self, n: usize) -> (usize, usize) {
+        let top = self.top + n * (SLIDER + GAP);
+        (top, top + SLIDER)
+    }
+
+    fn bar(&self) -> (usize, usize) {
+        let top = self.top + SLIDERS * (SLIDER + GAP);
+        (top, top + BAR)
+    }
+
+    fn markers(&self) -> (usize, usize) {
+        let top = self.bar().1 + GAP;
+        (top, top + MARKER)
+    }
+
+    fn hit(&self, x: usize, y: usize) -> Option<Part> {
+        if x + PICK < self.left || x >= self.right + PICK {
+            return None;
+        }
+        let within = |(top, bottom): (usize, usize)| y + GAP / 2 >= top && y < bottom + GAP / 2;
+        if within(self.bar()) {
+            return Some(Part::Bar);
+        }
+        if within(self.markers()) {
+            return Some(Part::Markers);
+        }
+        (0 .. SLIDERS).find(|&n| within(self.slider(n))).map(Part::Slider)
+    }
+
+    /// Gradient position, or slider value, from 0 to 1 at `x`.
+    fn value(&self, x: usize) -> f64 {
+        let x = cmp::min(cmp::max(x, self.left), self.right - 1);
+        (x - self.left) as f64 / (self.right - self.left) as f64
+    }
+
+    fn x(&self, value: f64) -> usize {
+        let x = self.left + (value.max(0.0).min(1.0) * (self.right - self.left) as f64 + 0.5) as usize;
+        cmp::min(x, self.right - 1)
+    }
+}
+
+/// Overlay to edit the current gradient with the mouse. Click the bar to add a stop, drag the
+/// markers under it to move them, right click or Delete removes one. The sliders recolour the
+/// selected stop and set the offset and density, S saves the gradient as JSON.
+pub struct Editor{
+    pub open: bool,
+    selected: Option<usize>,
+    drag: Option<Drag>,
+}
+
+impl Editor {
+    pub fn new() -> Editor{
+        Editor{
+            open: false,
+            selected: None,
+            drag: None,
+        }
+    }
+
+    /// Whether a stop or slider is being dragged, the mouse isn't panning the view then.
+    pub fn dragging(&self) -> bool {
+        self.open && self.drag.is_some()
+    }
+
+    /// Applies `event` to `gradient` if it's meant for the editor, returns whether it was.
+    pub fn handle(&mut self, event: &Event, gradient: &mut Gradient, size: (usize, usize)) -> bool {
+        if !self.open {
+            return false;
+        }
+        let layout = match Layout::new(size) {
+            Some(layout) => layout,
+            None => return false,
+        };
+        if self.selected.map_or(false, |n| n >= gradient.stops.len()) {
+            self.selected = None;
+        }
+
+        match *event {
+            Event::MouseButtonDown{ mouse_btn: Mouse::Left, x, y, .. } => {
+                let (x, y) = (cmp::max(x, 0) as usize, cmp::max(y, 0) as usize);
+                match layout.hit(x, y) {
+                    Some(Part::Slider(n)) => {
+                        self.drag = Some(Drag::Slider(n));
+                        self.slide(n, layout.value(x), gradient);
+                    },
+                    Some(Part::Bar) => {
+                        let position = layout.value(x);
+                        let stop = Stop{ position: position, color: gradient.color_at(position) };
+                        gradient.stops.push(stop);
+                        self.selected = Some(gradient.stops.len() - 1);
+                        self.drag = Some(Drag::Stop);
+                        self.update(gradient);
+                    },
+                    Some(Part::Markers) => {
+                        self.selected = self.pick(&layout, gradient, x);
+                        if self.selected.is_some() {
+                            self.drag = Some(Drag::Stop);
+                        }
+                    },
+                    None => return false,
+                }
+                true
+            },
+            Event::MouseButtonDown{ mouse_btn: Mouse::Right, x, y, .. } => {
+                let (x, y) = (cmp::max(x, 0) as usize, cmp::max(y, 0) as usize);
+                if layout.hit(x, y) != Some(Part::Markers) {
+                    return false;
+                }
+                if let Some(n) = self.pick(&layout, gradient, x) {
+                    self.remove(n, gradient);
+                }
+                true
+            },
+            Event::MouseButtonUp{ mouse_btn: Mouse::Left, .. } => self.drag.take().is_some(),
+            Event::MouseMotion{ x, .. } => {
+                let value = layout.value(cmp::max(x, 0) as usize);
+                match self.drag {
+                    Some(Drag::Stop) => {
+                        if let Some(n) = self.selected {
+                            gradient.stops[n].position = value;
+                            self.update(gradient);
+                        }
+                    },
+                    Some(Drag::Slider(n)) => self.slide(n, value, gradient),
+                    None => return false,
+                }
+                true
+            },
+            Event::KeyDown{ keycode: Some(Keycode::Delete), .. } => {
+                if let Some(n) = self.selected {
+                    self.remove(n, gradient);
+                }
+                true
+            },
+            Event::KeyDown{ keycode: Some(Keycode::S), .. } => {
+                let path = format!("{}.json", gradient.name);
+                match gradient.save(&path) {
+                    Ok(()) => println!("Saved palette to {}", path),
+                    Err(err) => println!("{}", err),
+                }
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Stop whose marker is closest to `x`, if any is close enough.
+    fn pick(&self, layout: &Layout, gradient: &Gradient, x: usize) -> Option<usize> {
+        let distance = |stop: &Stop| {
+            let marker = layout.x(stop.position);
+            if marker > x { marker - x } else { x - marker }
+        };
+        let mut closest = None;
+        for (n, stop) in gradient.stops.iter().enumerate() {
+            if distance(stop) <= closest.map_or(PICK, |m: usize| distance(&gradient.stops[m])) {
+                closest = Some(n);
+            }
+        }
+        closest
+    }
+
+    fn remove(&mut self, n: usize, gradient: &mut Gradient) {
+        // A gradient needs at least one colour.
+        if gradient.stops.len() > 1 {
+            gradient.stops.remove(n);
+            gradient.update();
+        }
+        self.selected = None;
+    }
+
+    fn slide(&mut self, n: usize, value: f64, gradient: &mut Gradient) {
+        match n {
+            OFFSET => gradient.offset = value,
+            DENSITY => gradient.density = DENSITY_RANGE.powf(value * 2.0 - 1.0),
+            channel => match self.selected {
+                Some(stop) => gradient.stops[stop].color[channel] = (value * 255.0 + 0.5) as u8,
+                None => return,
+            },
+        }
+        self.update(gradient);
+    }
+
+    /// Recomputes the gradient and keeps the selection on the same stop as they get sorted.
+    fn update(&mut self, gradient: &mut Gradient) {
+        let selected = self.selected.map(|n| gradient.stops[n]);
+        gradient.update();
+        if let Some(stop) = selected {
+            self.selected = gradient.stops.iter().position(|other| *other == stop);
+        }
+    }
+
+    /// Draws the editor over the RGBA pixels of a `width` x `heigth` frame, bottom row first.
+    pub fn draw(&self, gradient: &Gradient, pbo: &mut [u8], width: usize, heigth: usize) {
+        let layout = match Layout::new((width, heigth)) {
+            Some(layout) => layout,
+            None => return,
+        };
+        let selected = self.selected.and_then(|n| gradient.stops.get(n));
+        let index = |x: usize, y: usize| ((heigth - 1 - y) * width + x) * 4;
+
+        // Darken the background so the editor stays readable over any image.
+        for y in layout.top - GAP .. layout.markers().1 + GAP {
+            for x in layout.left - GAP .. layout.right + GAP {
+                let n = index(x, y);
+                for c in 0 .. 3 {
+                    pbo[n + c] /= 3;
+                }
+            }
+        }
+
+        for n in 0 .. SLIDERS {
+            let knob = match n {
+                OFFSET => Some(gradient.offset - gradient.offset.floor()),
+                DENSITY => Some((gradient.density.ln() / DENSITY_RANGE.ln() + 1.0) / 2.0),
+                channel => selected.map(|stop| stop.color[channel] as f64 / 255.0),
+            };
+            let (top, bottom) = layout.slider(n);
+            for x in layout.left .. layout.right {
+                let value = layout.value(x);
+                let level = (value * 255.0) as u8;
+                let color = match n {
+                    OFFSET => gradient.color_at(value),
+                    DENSITY => [level, level, level],
+                    channel => match selected {
+                        Some(stop) => {
+                            let mut color = stop.color;
+                            color[channel] = level;
+                            color
+                        },
+                        None => [level / 4, level / 4, level / 4],
+                    },
+                };
+                for y in top .. bottom {
+                    put(&mut pbo[index(x, y) ..], color);
+                }
+            }
+            if let Some(knob) = knob {
+                let x = layout.x(knob);
+                for y in top - 1 .. bottom + 1 {
+                    put(&mut pbo[index(x, y) ..], [255, 255, 255]);
+                }
+            }
+        }
+
+        let (top, bottom) = layout.bar();
+        for x in layout.left .. layout.right {
+            let color = gradient.color_at(layout.value(x));
+            for y in top .. bottom {
+                put(&mut pbo[index(x, y) ..], color);
+            }
+        }
+
+        let (top, bottom) = layout.markers();
+        for (n, stop) in gradient.stops.iter().enumerate() {
+            let center = layout.x(stop.position);
+            let border = if Some(n) == self.selected { [255, 255, 255] } else { [96, 96, 96] };
+            for x in center.saturating_sub(MARKER / 2) .. cmp::min(center + MARKER / 2 + 1, layout.right + GAP) {
+                for y in top .. bottom {
+                    let edge = x + MARKER / 2 == center || x == center + MARKER / 2 || y == top || y == bottom - 1;
+                    put(&mut pbo[index(x, y) ..], if edge { border } else { stop.color });
+                }
+            }
+        }
+    }
+}
+
+fn put(pixel: &mut [u8], color: [u8; 3]) {
+    pixel[0] = color[0];
+    pixel[1] = color[1];
+    pixel[2] = color[2];
+    pixel[3] = 255;
+}
+
+#[test]
+fn test_layout(){
+    let layout = Layout::new((800, 600)).unwrap();
+    assert_eq!(layout.hit(400, layout.bar().0 + 1), Some(Part::Bar));
+    assert_eq!(layout.hit(400, layout.markers().0 + 1), Some(Part::Markers));
+    assert_eq!(layout.hit(400, layout.slider(DENSITY).0), Some(Part::Slider(DENSITY)));
+    assert_eq!(layout.hit(400, layout.top - MARGIN), None);
+    assert_eq!(layout.hit(2, layout.bar().0 + 1), None);
+    assert_eq!(layout.value(layout.left), 0.0);
+    assert_eq!(layout.x(layout.value(400)), 400);
+    assert!(Layout::new((800, 50)).is_none());
+}
@@ -0,0 +1,180 @@
+use std::cmp;
+use std::path::Path;
+
+use options::*;
+use picture::*;
+use gradient::*;
+use coloring::{srgb_to_linear, linear_to_srgb, quantize};
+
+/// Points averaged into each colour sampled along a line.
+const LINE_SAMPLES: usize = 8;
+
+/// Pixels k-means looks at at most, larger pictures are subsampled.
+const KMEANS_PIXELS: usize = 20000;
+const KMEANS_ROUNDS: usize = 20;
+
+/// How a gradient is derived from a picture.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Method{
+    /// Colours along a line between two points, in 0..1 picture coordinates.
+    Line((f64, f64), (f64, f64)),
+    /// Dominant colours found by k-means clustering, from dark to light.
+    KMeans,
+}
+
+/// Gradient from a picture, set up by `--extract`, `--extract-line` and `--extract-colors`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Extraction{
+    pub method: Method,
+    pub colors: usize,
+}
+
+impl Extraction {
+    pub fn from_options(options: &Options) -> Result<Extraction, String> {
+        let line = match options.get("extract-line") {
+            Some(line) => {
+                let values = try!(line.split(',').map(|value| value.trim().parse::<f64>()).collect::<Result<Vec<f64>, _>>()
+                    .map_err(|_| format!("Invalid value for --extract-line: {}", line)));
+                if values.len() != 4 {
+                    return Err(format!("Invalid value for --extract-line: {}", line));
+                }
+                ((values[0], values[1]), (values[2], values[3]))
+            },
+            None => ((0.0, 0.5), (1.0, 0.5)),
+        };
+        let method = match options.get("extract").unwrap_or("kmeans") {
+            "line" => Method::Line(line.0, line.1),
+            "kmeans" => Method::KMeans,
+            name => return Err(format!("Unknown extraction method: {}", name)),
+        };
+        let colors = try!(options.get_or("extract-colors", 8));
+        if colors < 2 || colors > 256 {
+            return Err("Extracted palettes need between 2 and 256 colours".to_string());
+        }
+        Ok(Extraction{
+            method: method,
+            colors: colors,
+        })
+    }
+
+    /// Whether `path` is a picture rather than a palette file.
+    pub fn is_picture(path: &str) -> bool {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => {
+                let ext = ext.to_lowercase();
+                ["png", "ppm", "pnm", "jpg", "jpeg", "bmp", "gif"].contains(&&ext[..])
+            },
+            None => false,
+        }
+    }
+
+    pub fn load(&self, path: &str) -> Result<Gradient, String> {
+        let picture = try!(Picture::load(path));
+        if picture.width == 0 || picture.height == 0 {
+            return Err(format!("Unable to extract a palette from {}: empty image", path));
+        }
+        let name = Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or("picture");
+        Ok(self.extract(&picture, name))
+    }
+
+    pub fn extract(&self, picture: &Picture, name: &str) -> Gradient {
+        let colors = match self.method {
+            Method::Line(from, to) => along_line(picture, from, to, self.colors),
+            Method::KMeans => kmeans(picture, self.colors),
+        };
+        let mut gradient = Gradient::even(name, &colors);
+        gradient.space = ColorSpace::Oklab;
+        gradient.update();
+        gradient
+    }
+}
+
+/// Averages `count` stretches of the line from `from` to `to`, in linear light.
+fn along_line(picture: &Picture, from: (f64, f64), to: (f64, f64), count: usize) -> Vec<u8> {
+    let clamp = |value: f64| value.max(0.0).min(1.0);
+    let mut colors = Vec::with_capacity(count * 3);
+    for n in 0 .. count {
+        let mut sum = [0.0; 3];
+        for k in 0 .. LINE_SAMPLES {
+            let t = (n * LINE_SAMPLES + k) as f64 / (count * LINE_SAMPLES - 1) as f64;
+            let (u, v) = (clamp(from.0 + (to.0 - from.0) * t), clamp(from.1 + (to.1 - from.1) * t));
+            let x = cmp::min((u * picture.width as f64) as usize, picture.width - 1);
+            let y = cmp::min((v * picture.height as f64) as usize, picture.height - 1);
+            let pixel = picture.pixel(x, y);
+            for c in 0 .. 3 {
+                sum[c] += srgb_to_linear(pixel[c] as f64 / 255.0);
+            }
+        }
+        for c in 0 .. 3 {
+            colors.push(quantize(linear_to_srgb(sum[c] / LINE_SAMPLES as f64)));
+        }
+    }
+    colors
+}
+
+/// Clusters the opaque pixels into `count` colours in OKLab, so clusters follow perceived
+/// differences, and orders them by lightness. Clusters left empty are dropped.
+fn kmeans(picture: &Picture, count: usize) -> Vec<u8> {
+    let stride = (picture.width * picture.height + KMEANS_PIXELS - 1) / KMEANS_PIXELS;
+    let mut pixels: Vec<[f64; 3]> = picture.data.chunks(4).enumerate()
+        .filter(|&(n, pixel)| n % cmp::max(stride, 1) == 0 && pixel[3] >= 128)
+        .map(|(_, pixel)| ColorSpace::Oklab.to_space([pixel[0], pixel[1], pixel[2]]))
+        .collect();
+    if pixels.is_empty() {
+        return vec![0; 3];
+    }
+
+    // Starting from lightness quantiles keeps the result the same from run to run.
+    pixels.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap_or(::std::cmp::Ordering::Equal));
+    let mut centers: Vec<[f64; 3]> = (0 .. count).map(|n| pixels[(2 * n + 1) * pixels.len() / (2 * count)]).collect();
+    let mut sizes = vec![0; count];
+    for _ in 0 .. KMEANS_ROUNDS {
+        let mut sums = vec![[0.0; 3]; count];
+        sizes = vec![0; count];
+        for pixel in &pixels {
+            let distance = |center: &[f64; 3]| (0 .. 3).map(|c| (pixel[c] - center[c]) * (pixel[c] - center[c])).fold(0.0, |a, b| a + b);
+            let mut nearest = 0;
+            for n in 1 .. count {
+                if distance(&centers[n]) < distance(&centers[nearest]) {
+                    nearest = n;
+                }
+            }
+            for c in 0 .. 3 {
+                sums[nearest][c] += pixel[c];
+            }
+            sizes[nearest] += 1;
+        }
+        for n in 0 .. count {
+            if sizes[n] > 0 {
+                for c in 0 .. 3 {
+                    centers[n][c] = sums[n][c] / sizes[n] as f64;
+                }
+            }
+        }
+    }
+
+    let mut clusters: Vec<[f64; 3]> = centers.into_iter().zip(sizes.into_iter()).filter(|&(_, size)| size > 0).map(|(center, _)| center).collect();
+    clusters.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap_or(::std::cmp::Ordering::Equal));
+    clusters.dedup();
+    clusters.iter().flat_map(|center| ColorSpace::Oklab.to_rgb(*center).to_vec().into_iter()).collect()
+}
+
+#[test]
+fn test_extract(){
+    // Left half dark blue, right half light orange.
+    let mut data = Vec::new();
+    for _ in 0 .. 4 {
+        for x in 0 .. 8 {
+            let pixel = if x < 4 { [10, 20, 90, 255] } else { [250, 180, 60, 255] };
+            data.extend(pixel.iter().cloned());
+        }
+    }
+    let picture = Picture{ width: 8, height: 4, data: data };
+
+    let colors = kmeans(&picture, 4);
+    assert_eq!(colors, vec![10, 20, 90, 250, 180, 60]);
+
+    let colors = along_line(&picture, (0.0, 0.5), (1.0, 0.5), 2);
+    assert_eq!(&colors[0 .. 3], &[10, 20, 90]);
+    assert_eq!(&colors[3 .. 6], &[250, 180, 60]);
+}
@@ -0,0 +1,138 @@
+use newton::*;
+use lyapunov::*;
+use buddhabrot::*;
+use options::*;
+
+pub const USAGE: &'static str = "Usage:
+    mandelrust [mandelbrot]
+    mandelrust newton (--coeffs <c0,c1,...> | --roots <r0,r1,...>) [--iterations <n>]
+    mandelrust lyapunov [--sequence <AB...>] [--warmup <n>] [--iterations <n>]
+    mandelrust (buddhabrot | anti-buddhabrot) [--iterations <n>] [--samples <n>]
+    mandelrust nebulabrot [--limits <red,green,blue>] [--samples <n>]
+
+Coloring options:
+    --coloring (smooth | trap | tia | stripe | decomposition | field-lines | histogram)
+    --stripe-density <f> --decomposition <sectors> --field-lines <rays>
+    --trap (point | line | cross | circle | <image>) [--trap-center <c>] [--trap-size <f>] [--trap-angle <degrees>]
+
+Palette options:
+    --palette <name | file>     Palette to start with: a .map, .ggr or .json file, a picture to
+                                extract it from, a generated one like harmonious-<seed> or
+                                cosine-<seed>, or cosine:<a>,<b>,<c>,<d> with the red, green
+                                and blue of a + b*cos(2pi(c*t + d)) each
+    --generator (harmonious | cosine)
+                                Kind of palettes N generates
+    --extract (kmeans | line)   Take the dominant colours of a --palette picture, dark to light,
+                                or the colours along a line through it, kmeans by default
+    --extract-line <x0,y0,x1,y1>
+                                Line from 0 to 1 across the picture, across the middle by default
+    --extract-colors <n>        Number of colours to extract, 8 by default
+    --palettes <dir>            Directory of palettes to choose from at runtime
+    --interpolation (srgb | linear | oklab | lch)
+                                Colour space gradients are blended in, linear by default
+    --curve (linear | catmull-rom | monotone)
+                                Curve through the gradient stops, linear by default
+    --transfer (linear | log | sqrt | cbrt)
+                                Applied to the colouring value before the gradient lookup
+    --density <f>               Loops through the gradient per period, 1 by default
+    --offset <f>                Shift of the gradient, in loops
+
+Layer options:
+    --layers <layer>;...        Layers composited over the colouring, bottom first, each one
+                                <input>[:<blend>[:<opacity>[:<palette>]]] with the input
+                                smooth, iteration, magnitude or angle and the blend mode
+                                normal, multiply, screen or overlay
+
+Antialiasing options:
+    --antialiasing (off | grid | jittered | adaptive) [--supersampling <n>]
+
+Other options:
+    --threads <n>       Worker threads, one per CPU by default
+    --fill (scan | rectangles)
+                        Rectangle fill skips uniform areas, but can miss small details
+    --cycle <speed>     Start colour cycling, in gradient loops per second
+    --dither (off | ordered | blue-noise)
+                        Dithering of the colours shown, against banding, ordered by default
+    --gamma (linear | legacy)
+                        Mix colours in linear light, or on sRGB values like older versions
+                        did to match their images, linear by default. Legacy also blends
+                        every palette in sRGB and rounds colours down instead of dithering,
+                        unless --interpolation or --dither say otherwise
+
+Complex numbers are written as a, bi or a+bi, coefficients go from the highest degree down,
+e.g. `newton --coeffs 1,0,0,-1` renders z^3 - 1.
+Lyapunov sequences are strings of A and B, e.g. `lyapunov --sequence AABAB`.
+Press C to cycle through colorings, G through palettes, A through antialiasing modes and F to toggle rectangle fill.
+Space starts and stops colour cycling, R reverses it, `.` and `,` make it faster or slower.
+L turns the layers off and on again.
+P saves the view as a 16-bit PPM, mandelrust-<time>.ppm.
+N generates a new palette, its name has the seed to get it back with --palette.
+T cycles through transfer functions, [ and ] change the density and - and = the offset.
+E opens the gradient editor: click the bar to add a stop, drag the markers below it to move
+stops and right click them or press Delete to remove them. The sliders above set the red, green
+and blue of the selected stop, the offset and the density. S saves the gradient as <name>.json.";
+
+pub enum Fractal{
+    Mandelbrot,
+    Newton(Newton),
+    Lyapunov(Lyapunov),
+    Buddhabrot(Buddhabrot),
+}
+
+impl Fractal {
+    pub fn from_options(options: &Options) -> Result<Fractal, String> {
+        let name = options.name().unwrap_or("mandelbrot");
+        match name {
+            "mandelbrot" => Ok(Fractal::Mandelbrot),
+            "newton" => {
+                let poly = match (options.get("coeffs"), options.get("roots")) {
+                    (Some(coeffs), None) => Polynomial::new(try!(parse_list(coeffs))),
+                    (None, Some(roots)) => Polynomial::from_roots(&try!(parse_list(roots))),
+                    _ => return Err("Newton fractal needs either --coeffs or --roots".to_string()),
+                };
+                if poly.degree() < 2 {
+                    return Err("Newton fractal needs a polynomial of degree 2 or higher".to_string());
+                }
+                let max_iter = try!(options.get_or("iterations", 64));
+                Ok(Fractal::Newton(Newton::new(poly, max_iter)))
+            },
+            "lyapunov" => {
+                let sequence = options.get("sequence").unwrap_or("AB");
+                let warmup = try!(options.get_or("warmup", 200));
+                let iterations = try!(options.get_or("iterations", 1000));
+                Ok(Fractal::Lyapunov(try!(Lyapunov::new(sequence, warmup, iterations))))
+            },
+            "buddhabrot" | "anti-buddhabrot" => {
+                let mode = if name == "buddhabrot" { DensityMode::Buddhabrot } else { DensityMode::AntiBuddhabrot };
+                let limit = try!(options.get_or("iterations", if name == "buddhabrot" { 1000 } else { 100 }));
+                let samples = try!(options.get_or("samples", 20000));
+                Ok(Fractal::Buddhabrot(Buddhabrot::new(mode, [limit; 3], samples)))
+            },
+            "nebulabrot" => {
+                let limits = match options.get("limits") {
+                    Some(limits) => {
+                        let limits = try!(limits.split(',').map(|s| s.parse::<usize>().map_err(|_| format!("Invalid iteration limit: {}", s))).collect::<Result<Vec<usize>, String>>());
+                        if limits.len() != 3 {
+                            return Err("Nebulabrot needs exactly three iteration limits".to_string());
+                        }
+                        [limits[0], limits[1], limits[2]]
+                    },
+                    None => [5000, 500, 50],
+                };
+                let samples = try!(options.get_or("samples", 20000));
+                Ok(Fractal::Buddhabrot(Buddhabrot::new(DensityMode::Nebulabrot, limits, samples)))
+            },
+            name => Err(format!("Unknown fractal: {}", name)),
+        }
+    }
+
+    /// Point in the middle of the window, in the same negated form `main` keeps its `center` in.
+    pub fn default_center(&self) -> (f32, f32) {
+        match *self {
+            Fractal::Mandelbrot => (0.5, 0.0),
+            Fractal::Newton(_) => (0.0, 0.0),
+            Fractal::Lyapunov(_) => (-3.0, -3.0),
+            Fractal::Buddhabrot(_) => (0.5, 0.0),
+        }
+    }
+}
@@ -0,0 +1,865 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use rustc_serialize::json::Json;
+
+use options::*;
+use procedural;
+use extract::*;
+use coloring::{Rgb, Gamma, srgb_to_linear, linear_to_srgb, quantize, to_bytes};
+
+const DEFAULT: [u8; 48] = [
+9, 1, 47,
+4, 4, 73,
+0, 7, 100,
+12, 44, 138,
+24, 82, 177,
+57, 125, 209,
+134, 181, 229,
+211, 236, 248,
+241, 233, 191,
+248, 201, 95,
+255, 170, 0,
+204, 128, 0,
+153, 87, 0,
+106, 52, 3,
+66, 30, 15,
+25, 7, 26,
+];
+
+const RAINBOW: [u8; 36] = [
+255, 0, 0,
+127, 127, 127,
+255, 255, 0,
+127, 127, 127,
+0, 255, 0,
+127, 127, 127,
+0, 255, 255,
+127, 127, 127,
+0, 0, 255,
+127, 127, 127,
+255, 0, 255,
+127, 127, 127,
+];
+
+/// Gradient for the chaotic regions of Lyapunov fractals.
+pub const CHAOS: [u8; 24] = [
+0, 0, 0,
+8, 12, 40,
+22, 30, 90,
+40, 60, 140,
+70, 110, 190,
+120, 170, 220,
+190, 220, 240,
+255, 255, 255,
+];
+
+/// Number of colours GIMP gradients are sampled to, and the default period of JSON gradients.
+const RESOLUTION: usize = 256;
+
+/// Number of colours precomputed for each loop of a gradient.
+const TABLE_SIZE: usize = 1024;
+
+/// Colour space gradients are blended in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorSpace{
+    /// Straight between sRGB byte values, the way gradients always used to be blended.
+    Srgb,
+    /// Linear light, midpoints between saturated colours keep their brightness. The default.
+    LinearRgb,
+    /// Perceptually uniform OKLab, even steps in lightness and no muddy midpoints.
+    Oklab,
+    /// Lightness, chroma and hue of OKLab, hues go round the shorter way.
+    Lch,
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Result<ColorSpace, String> {
+        match name {
+            "srgb" => Ok(ColorSpace::Srgb),
+            "linear" => Ok(ColorSpace::LinearRgb),
+            "oklab" => Ok(ColorSpace::Oklab),
+            "lch" => Ok(ColorSpace::Lch),
+            name => Err(format!("Unknown interpolation: {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::LinearRgb => "linear",
+            ColorSpace::Oklab => "oklab",
+            ColorSpace::Lch => "lch",
+        }
+    }
+
+    pub fn to_space(self, color: [u8; 3]) -> [f64; 3] {
+        let srgb = [color[0] as f64 / 255.0, color[1] as f64 / 255.0, color[2] as f64 / 255.0];
+        let linear = [srgb_to_linear(srgb[0]), srgb_to_linear(srgb[1]), srgb_to_linear(srgb[2])];
+        match self {
+            ColorSpace::Srgb => srgb,
+            ColorSpace::LinearRgb => linear,
+            ColorSpace::Oklab => linear_to_oklab(linear),
+            ColorSpace::Lch => {
+                let lab = linear_to_oklab(linear);
+                [lab[0], lab[1].hypot(lab[2]), lab[2].atan2(lab[1])]
+            },
+        }
+    }
+
+    pub fn to_rgb(self, value: [f64; 3]) -> [u8; 3] {
+        let srgb = self.to_srgb(value);
+        [quantize(srgb[0]), quantize(srgb[1]), quantize(srgb[2])]
+    }
+
+    /// sRGB encoded channels from 0 to 1, out of gamut colours are clipped.
+    pub fn to_srgb(self, value: [f64; 3]) -> [f64; 3] {
+        if self == ColorSpace::Srgb {
+            return [value[0].max(0.0).min(1.0), value[1].max(0.0).min(1.0), value[2].max(0.0).min(1.0)];
+        }
+        let linear = self.to_linear(value);
+        [linear_to_srgb(linear[0]), linear_to_srgb(linear[1]), linear_to_srgb(linear[2])]
+    }
+
+    /// Linear light channels from 0 to 1, out of gamut colours are clipped.
+    pub fn to_linear(self, value: [f64; 3]) -> [f64; 3] {
+        let linear = match self {
+            ColorSpace::Srgb => return [
+                srgb_to_linear(value[0].max(0.0).min(1.0)),
+                srgb_to_linear(value[1].max(0.0).min(1.0)),
+                srgb_to_linear(value[2].max(0.0).min(1.0)),
+            ],
+            ColorSpace::LinearRgb => value,
+            ColorSpace::Oklab => oklab_to_linear(value),
+            ColorSpace::Lch => oklab_to_linear([value[0], value[1] * value[2].cos(), value[1] * value[2].sin()]),
+        };
+        [linear[0].max(0.0).min(1.0), linear[1].max(0.0).min(1.0), linear[2].max(0.0).min(1.0)]
+    }
+}
+
+/// Curve the colour channels follow through the stops.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Curve{
+    /// Straight lines, with a visible kink at every stop.
+    Linear,
+    /// Catmull-Rom spline, smooth but overshoots around sharp changes.
+    CatmullRom,
+    /// Monotone cubic, smooth and never leaves the range of the two stops around it.
+    Monotone,
+}
+
+impl Curve {
+    pub fn from_name(name: &str) -> Result<Curve, String> {
+        match name {
+            "linear" => Ok(Curve::Linear),
+            "catmull-rom" => Ok(Curve::CatmullRom),
+            "monotone" => Ok(Curve::Monotone),
+            name => Err(format!("Unknown curve: {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Curve::Linear => "linear",
+            Curve::CatmullRom => "catmull-rom",
+            Curve::Monotone => "monotone",
+        }
+    }
+}
+
+/// Function the colouring value goes through before it is looked up in the gradient. Values
+/// that grow quickly, like iteration counts deep in a zoom, get spread over fewer colours.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Transfer{
+    Linear,
+    /// `ln(1 + x)`
+    Log,
+    Sqrt,
+    Cbrt,
+}
+
+impl Transfer {
+    pub fn from_name(name: &str) -> Result<Transfer, String> {
+        match name {
+            "linear" => Ok(Transfer::Linear),
+            "log" => Ok(Transfer::Log),
+            "sqrt" => Ok(Transfer::Sqrt),
+            "cbrt" => Ok(Transfer::Cbrt),
+            name => Err(format!("Unknown transfer function: {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Transfer::Linear => "linear",
+            Transfer::Log => "log",
+            Transfer::Sqrt => "sqrt",
+            Transfer::Cbrt => "cbrt",
+        }
+    }
+
+    /// The one after this, for cycling through them.
+    pub fn next(&self) -> Transfer {
+        match *self {
+            Transfer::Linear => Transfer::Log,
+            Transfer::Log => Transfer::Sqrt,
+            Transfer::Sqrt => Transfer::Cbrt,
+            Transfer::Cbrt => Transfer::Linear,
+        }
+    }
+
+    /// Negative values are mapped like positive ones, keeping their sign.
+    pub fn apply(&self, value: f64) -> f64 {
+        let magnitude = value.abs();
+        let mapped = match *self {
+            Transfer::Linear => magnitude,
+            Transfer::Log => magnitude.ln_1p(),
+            Transfer::Sqrt => magnitude.sqrt(),
+            Transfer::Cbrt => magnitude.cbrt(),
+        };
+        if value < 0.0 { -mapped } else { mapped }
+    }
+}
+
+/// Colour at a position in the gradient, from 0 to 1.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Stop{
+    pub position: f64,
+    pub color: [u8; 3],
+}
+
+/// Looping gradient through stops at arbitrary positions. After the last stop it blends back
+/// into the first one.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Gradient{
+    pub name: String,
+    /// Sorted by position.
+    pub stops: Vec<Stop>,
+    pub space: ColorSpace,
+    pub curve: Curve,
+    /// Units of the `interpolate` input one loop through the gradient takes.
+    pub period: f64,
+    /// Shift of the whole gradient, in loops.
+    pub offset: f64,
+    /// Loops through the gradient per `period`, higher values pack the colours closer together.
+    pub density: f64,
+    pub transfer: Transfer,
+    /// `TABLE_SIZE` colours of one loop, rebuilt by `update`, mixed the way `blending` says.
+    /// Kept as floats, quantising them here would band smooth gradients.
+    table: Vec<f32>,
+}
+
+impl Gradient {
+    pub fn new(name: &str, stops: Vec<Stop>, period: f64) -> Gradient{
+        let mut gradient = Gradient{
+            name: name.to_string(),
+            stops: stops,
+            space: ColorSpace::LinearRgb,
+            curve: Curve::Linear,
+            period: period,
+            offset: 0.0,
+            density: 1.0,
+            transfer: Transfer::Linear,
+            table: Vec::new(),
+        };
+        gradient.update();
+        gradient
+    }
+
+    /// Evenly spaced colours given as RGB triplets, one stop of `interpolate` input apart.
+    pub fn even(name: &str, colors: &[u8]) -> Gradient{
+        let count = colors.len() / 3;
+        let stops = colors.chunks(3).enumerate().map(|(n, color)| Stop{
+            position: n as f64 / count as f64,
+            color: [color[0], color[1], color[2]],
+        }).collect();
+        Gradient::new(name, stops, count as f64)
+    }
+
+    pub fn builtin() -> Vec<Gradient> {
+        vec![Gradient::even("default", &DEFAULT), Gradient::even("rainbow", &RAINBOW)]
+    }
+
+    /// `interpolate` input at which the last stop is reached.
+    pub fn span(&self) -> f64 {
+        self.stops.last().map_or(0.0, |stop| stop.position) * self.period
+    }
+
+    /// Mapping stage in front of the gradient lookup: position, in loops through the gradient,
+    /// of the colouring value `color`.
+    pub fn map(&self, color: f64) -> f64 {
+        self.transfer.apply(color) * self.density / self.period + self.offset
+    }
+
+    /// Colour at `position` of one loop through the gradient, from 0 to 1.
+    pub fn color_at(&self, position: f64) -> [u8; 3] {
+        let n = ((position - position.floor()) * TABLE_SIZE as f64) as usize % TABLE_SIZE * 3;
+        let color = self.blending().encode((self.table[n] as f64, self.table[n + 1] as f64, self.table[n + 2] as f64));
+        [quantize(color.0), quantize(color.1), quantize(color.2)]
+    }
+
+    /// Colour at `position` of one loop through the gradient, mixed between the two closest
+    /// table entries and not quantised.
+    pub fn lookup(&self, position: f64) -> Rgb {
+        let table = &self.table;
+        let position = (position - position.floor()) * TABLE_SIZE as f64;
+        let fract = position.fract();
+        let n1 = position as usize % TABLE_SIZE * 3;
+        let n2 = (position as usize + 1) % TABLE_SIZE * 3;
+        let mix = |c: usize| table[n1 + c] as f64 * (1.0 - fract) + table[n2 + c] as f64 * fract;
+        self.blending().encode((mix(0), mix(1), mix(2)))
+    }
+
+    /// How colours close together on the gradient are mixed: in linear light, unless the
+    /// whole gradient is blended in sRGB.
+    pub fn blending(&self) -> Gamma {
+        if self.space == ColorSpace::Srgb { Gamma::Legacy } else { Gamma::Linear }
+    }
+
+    /// Sorts the stops and recomputes the colour table, has to be called after changing the
+    /// stops, colour space or curve.
+    pub fn update(&mut self) {
+        for stop in &mut self.stops {
+            stop.position = stop.position.max(0.0).min(1.0);
+        }
+        self.stops.sort_by(|a, b| a.position.partial_cmp(&b.position).unwrap_or(::std::cmp::Ordering::Equal));
+        self.table = build_table(&self.stops, self.space, self.curve);
+    }
+
+    /// Built in gradients, then those in `--palettes <dir>`. `--palette` picks the one to start
+    /// with by name, generates it, extracts it from a picture or loads it from a file, and puts
+    /// it first. The `Overrides` apply to all of them.
+    pub fn from_options(options: &Options) -> Result<Vec<Gradient>, String> {
+        let mut gradients = Gradient::builtin();
+        let extraction = try!(Extraction::from_options(options));
+        if let Some(dir) = options.get("palettes") {
+            gradients.extend(try!(Gradient::load_dir(dir)));
+        }
+        if let Some(palette) = options.get("palette") {
+            let n = match gradients.iter().position(|gradient| gradient.name == palette) {
+                Some(n) => n,
+                None => {
+                    gradients.push(try!(Gradient::named(palette, &extraction)));
+                    gradients.len() - 1
+                },
+            };
+            let first = gradients.remove(n);
+            gradients.insert(0, first);
+        }
+
+        let overrides = try!(Overrides::from_options(options));
+        for gradient in &mut gradients {
+            overrides.apply(gradient);
+        }
+        Ok(gradients)
+    }
+
+    /// Generates the palette `name`, extracts it from a picture or loads it from a file.
+    pub fn named(name: &str, extraction: &Extraction) -> Result<Gradient, String> {
+        match procedural::parse(name) {
+            Some(generated) => generated,
+            None if Extraction::is_picture(name) => extraction.load(name),
+            None => Gradient::load(name),
+        }
+    }
+
+    /// Loads a Fractint `.map`, GIMP `.ggr` or JSON stop list, named after the file.
+    pub fn load(path: &str) -> Result<Gradient, String> {
+        let mut text = String::new();
+        try!(fs::File::open(path).and_then(|mut file| file.read_to_string(&mut text)).map_err(|err| format!("Unable to load palette {}: {}", path, err)));
+
+        let path = Path::new(path);
+        let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("palette");
+        let gradient = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("map") => parse_map(&text).map(|colors| Gradient::even(name, &colors)),
+            Some("ggr") => parse_ggr(&text).map(|colors| Gradient::even(name, &colors)),
+            Some("json") => parse_json(&text).map(|gradient| Gradient{ name: name.to_string(), ..gradient }),
+            _ => Err("unknown palette format".to_string()),
+        };
+        gradient.map_err(|err| format!("Unable to load palette {}: {}", path.display(), err))
+    }
+
+    /// JSON stop list `load` reads back, with everything else that makes up the gradient.
+    pub fn to_json(&self) -> String {
+        let stops: Vec<String> = self.stops.iter().map(|stop| {
+            format!("        {{\"position\": {}, \"color\": \"#{:02x}{:02x}{:02x}\"}}", stop.position, stop.color[0], stop.color[1], stop.color[2])
+        }).collect();
+        format!("{{\n    \"interpolation\": \"{}\",\n    \"curve\": \"{}\",\n    \"period\": {},\n    \"offset\": {},\n    \"density\": {},\n    \"transfer\": \"{}\",\n    \"stops\": [\n{}\n    ]\n}}\n",
+            self.space.name(), self.curve.name(), self.period, self.offset, self.density, self.transfer.name(), stops.join(",\n"))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::File::create(path).and_then(|mut file| file.write_all(self.to_json().as_bytes())).map_err(|err| format!("Unable to save palette {}: {}", path, err))
+    }
+
+    /// Loads every palette file in `dir`, sorted by name. Other files are skipped, and so are
+    /// palette files that fail to load, with a warning.
+    pub fn load_dir(dir: &str) -> Result<Vec<Gradient>, String> {
+        let entries = try!(fs::read_dir(dir).map_err(|err| format!("Unable to read palette directory {}: {}", dir, err)));
+        let mut paths: Vec<String> = entries.filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()).map_or(false, |ext| ext == "map" || ext == "ggr" || ext == "json"))
+            .filter_map(|path| path.to_str().map(|path| path.to_string()))
+            .collect();
+        paths.sort();
+        Ok(paths.iter().filter_map(|path| match Gradient::load(path) {
+            Ok(gradient) => Some(gradient),
+            Err(err) => {
+                println!("{}, skipping it", err);
+                None
+            },
+        }).collect())
+    }
+}
+
+/// Settings from `--interpolation`, `--curve`, `--transfer`, `--density` and `--offset` that
+/// every gradient takes, including those generated or loaded after startup. `--gamma legacy`
+/// blends them all in sRGB unless `--interpolation` says otherwise.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Overrides{
+    pub space: Option<ColorSpace>,
+    pub curve: Option<Curve>,
+    pub transfer: Option<Transfer>,
+    pub density: Option<f64>,
+    pub offset: Option<f64>,
+}
+
+impl Overrides {
+    pub fn from_options(options: &Options) -> Result<Overrides, String> {
+        let gamma = try!(Gamma::from_options(options));
+        let space = match options.get("interpolation") {
+            Some(name) => Some(try!(ColorSpace::from_name(name))),
+            None if gamma == Gamma::Legacy => Some(ColorSpace::Srgb),
+            None => None,
+        };
+        let curve = match options.get("curve") {
+            Some(name) => Some(try!(Curve::from_name(name))),
+            None => None,
+        };
+        let transfer = match options.get("transfer") {
+            Some(name) => Some(try!(Transfer::from_name(name))),
+            None => None,
+        };
+        let density = match options.get("density") {
+            Some(_) => Some(try!(options.get_or("density", 1.0))),
+            None => None,
+        };
+        if density.map_or(false, |density| density <= 0.0) {
+            return Err("Density has to be positive".to_string());
+        }
+        let offset = match options.get("offset") {
+            Some(_) => Some(try!(options.get_or("offset", 0.0))),
+            None => None,
+        };
+        Ok(Overrides{
+            space: space,
+            curve: curve,
+            transfer: transfer,
+            density: density,
+            offset: offset,
+        })
+    }
+
+    pub fn apply(&self, gradient: &mut Gradient) {
+        if self.space.is_some() || self.curve.is_some() {
+            gradient.space = self.space.unwrap_or(gradient.space);
+            gradient.curve = self.curve.unwrap_or(gradient.curve);
+            gradient.update();
+        }
+        gradient.transfer = self.transfer.unwrap_or(gradient.transfer);
+        gradient.density = self.density.unwrap_or(gradient.density);
+        gradient.offset = self.offset.unwrap_or(gradient.offset);
+    }
+}
+
+/// Colour `color` units into `gradient`, shifted by `phase` times the whole gradient.
+pub fn interpolate(gradient: &Gradient, color: f64, phase: f64) -> Rgb {
+    gradient.lookup(gradient.map(color) + phase)
+}
+
+/// Colour `color` stops into the looping list of evenly spaced colours `tex`, mixed as `gamma`
+/// says. For fixed gradients like `CHAOS` that don't need a table.
+pub fn interpolate_colors(tex: &[u8], color: f64, phase: f64, gamma: Gamma) -> Rgb {
+    let color = color + phase * (tex.len() / 3) as f64;
+    let fract = color.fract();
+    let trunc = color.trunc() as usize;
+    let n1 = trunc%(tex.len()/3)*3;
+    let n2 = (trunc + 1)%(tex.len()/3)*3;
+    let col1 = gamma.decode((tex[n1] as f64 / 255.0, tex[n1+1] as f64 / 255.0, tex[n1+2] as f64 / 255.0));
+    let col2 = gamma.decode((tex[n2] as f64 / 255.0, tex[n2+1] as f64 / 255.0, tex[n2+2] as f64 / 255.0));
+    gamma.encode((
+        col2.0 * fract + col1.0 * (1.0-fract),
+        col2.1 * fract + col1.1 * (1.0-fract),
+        col2.2 * fract + col1.2 * (1.0-fract),
+    ))
+}
+
+fn build_table(stops: &[Stop], space: ColorSpace, curve: Curve) -> Vec<f32> {
+    if stops.is_empty() {
+        return vec![0.0; TABLE_SIZE * 3];
+    }
+    let values: Vec<[f64; 3]> = stops.iter().map(|stop| space.to_space(stop.color)).collect();
+    let count = stops.len() as isize;
+    // Stop `n` of the endless repetition of the gradient.
+    let point = |n: isize| {
+        let index = ((n % count) + count) % count;
+        (stops[index as usize].position + ((n - index) / count) as f64, values[index as usize])
+    };
+
+    let mut table = Vec::with_capacity(TABLE_SIZE * 3);
+    for entry in 0 .. TABLE_SIZE {
+        let x = entry as f64 / TABLE_SIZE as f64;
+        // Before the first stop we are still between the last one of the previous loop and it.
+        let n = stops.iter().rposition(|stop| stop.position <= x).map_or(-1, |n| n as isize);
+        let mut points = [point(n - 1), point(n), point(n + 1), point(n + 2)];
+        if space == ColorSpace::Lch {
+            // Greys have no hue of their own, they take the one of the colour they blend into.
+            let hue = if points[1].1[1] > 1e-4 { points[1].1[2] } else { points[2].1[2] };
+            for point in &mut points {
+                if point.1[1] <= 1e-4 {
+                    point.1[2] = hue;
+                }
+            }
+            for k in 1 .. 4 {
+                while points[k].1[2] - points[k - 1].1[2] > PI {
+                    points[k].1[2] -= 2.0 * PI;
+                }
+                while points[k].1[2] - points[k - 1].1[2] < -PI {
+                    points[k].1[2] += 2.0 * PI;
+                }
+            }
+        }
+        // Colours are kept in linear light so `interpolate` can mix neighbouring entries
+        // there, except for gradients blended in sRGB.
+        let value = evaluate(curve, &points, x);
+        let color = if space == ColorSpace::Srgb { space.to_srgb(value) } else { space.to_linear(value) };
+        table.extend(color.iter().map(|&value| value as f32));
+    }
+    table
+}
+
+/// Value at `x` of the curve segment between the middle two of four consecutive stops.
+fn evaluate(curve: Curve, points: &[(f64, [f64; 3]); 4], x: f64) -> [f64; 3] {
+    let (x1, x2) = (points[1].0, points[2].0);
+    let h = x2 - x1;
+    let t = if h > 0.0 { ((x - x1) / h).max(0.0).min(1.0) } else { 0.0 };
+    let mut value = [0.0; 3];
+    for c in 0 .. 3 {
+        let y = [points[0].1[c], points[1].1[c], points[2].1[c], points[3].1[c]];
+        value[c] = match curve {
+            Curve::Linear => y[1] + (y[2] - y[1]) * t,
+            _ => {
+                let m1 = tangent(curve, [points[0].0, x1, x2], [y[0], y[1], y[2]]);
+                let m2 = tangent(curve, [x1, x2, points[3].0], [y[1], y[2], y[3]]);
+                let (t2, t3) = (t * t, t * t * t);
+                (2.0 * t3 - 3.0 * t2 + 1.0) * y[1] + (t3 - 2.0 * t2 + t) * h * m1
+                    + (3.0 * t2 - 2.0 * t3) * y[2] + (t3 - t2) * h * m2
+            },
+        };
+    }
+    value
+}
+
+/// Slope of the curve at the middle of three consecutive stops.
+fn tangent(curve: Curve, x: [f64; 3], y: [f64; 3]) -> f64 {
+    let slope = |a: usize, b: usize| if x[b] > x[a] { (y[b] - y[a]) / (x[b] - x[a]) } else { 0.0 };
+    match curve {
+        Curve::Linear => slope(1, 2),
+        Curve::CatmullRom => slope(0, 2),
+        Curve::Monotone => {
+            // Weighted harmonic mean of the slopes on both sides, flat at extrema (Fritsch-Butland).
+            let (d0, d1) = (slope(0, 1), slope(1, 2));
+            if d0 * d1 <= 0.0 {
+                0.0
+            }else{
+                let (h0, h1) = (x[1] - x[0], x[2] - x[1]);
+                let (w0, w1) = (2.0 * h1 + h0, h1 + 2.0 * h0);
+                (w0 + w1) / (w0 / d0 + w1 / d1)
+            }
+        },
+    }
+}
+
+fn linear_to_oklab(rgb: [f64; 3]) -> [f64; 3] {
+    let l = (0.4122214708 * rgb[0] + 0.5363325363 * rgb[1] + 0.0514459929 * rgb[2]).cbrt();
+    let m = (0.2119034982 * rgb[0] + 0.6806995451 * rgb[1] + 0.1073969566 * rgb[2]).cbrt();
+    let s = (0.0883024619 * rgb[0] + 0.2817188376 * rgb[1] + 0.6299787005 * rgb[2]).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_linear(lab: [f64; 3]) -> [f64; 3] {
+    let l = (lab[0] + 0.3963377774 * lab[1] + 0.2158037573 * lab[2]).powi(3);
+    let m = (lab[0] - 0.1055613458 * lab[1] - 0.0638541728 * lab[2]).powi(3);
+    let s = (lab[0] - 0.0894841775 * lab[1] - 1.2914855480 * lab[2]).powi(3);
+    [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+}
+
+/// Fractint palettes: one `r g b` line per colour, anything after the third number is a comment.
+fn parse_map(text: &str) -> Result<Vec<u8>, String> {
+    let mut colors = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let values: Vec<&str> = line.split_whitespace().take(3).collect();
+        if values.len() < 3 {
+            return Err(format!("invalid line {}", line));
+        }
+        for value in values {
+            colors.push(try!(value.parse::<u8>().map_err(|_| format!("invalid colour value {}", value))));
+        }
+    }
+    if colors.is_empty() {
+        return Err("no colours".to_string());
+    }
+    Ok(colors)
+}
+
+/// GIMP gradients: segments with a left, middle and right position, the colours at both ends
+/// and a blending function. Transparency is ignored and HSV segments are blended as RGB.
+fn parse_ggr(text: &str) -> Result<Vec<u8>, String> {
+    let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty());
+    if lines.next() != Some("GIMP Gradient") {
+        return Err("missing GIMP Gradient header".to_string());
+    }
+    let mut line = lines.next();
+    if line.map_or(false, |line| line.starts_with("Name:")) {
+        line = lines.next();
+    }
+    let count = try!(line.and_then(|line| line.parse::<usize>().ok()).ok_or("missing segment count".to_string()));
+
+    let mut segments = Vec::new();
+    for line in lines.take(count) {
+        let values = try!(line.split_whitespace().map(|value| value.parse::<f64>()).collect::<Result<Vec<f64>, _>>().map_err(|_| format!("invalid segment {}", line)));
+        if values.len() < 12 {
+            return Err(format!("invalid segment {}", line));
+        }
+        segments.push(values);
+    }
+    if segments.len() != count || count == 0 {
+        return Err("missing segments".to_string());
+    }
+
+    let mut colors = Vec::with_capacity(RESOLUTION * 3);
+    for n in 0 .. RESOLUTION {
+        let x = n as f64 / RESOLUTION as f64;
+        let segment = segments.iter().find(|segment| x <= segment[2]).unwrap_or(&segments[count - 1]);
+        let (left, middle, right) = (segment[0], segment[1], segment[2]);
+        let t = if right > left { ((x - left) / (right - left)).max(0.0).min(1.0) } else { 0.0 };
+        let m = if right > left { (middle - left) / (right - left) } else { 0.5 };
+        let linear = if t <= m {
+            if m > 0.0 { 0.5 * t / m } else { 0.0 }
+        }else{
+            if m < 1.0 { 0.5 + 0.5 * (t - m) / (1.0 - m) } else { 1.0 }
+        };
+        let k = match segment[11] as u32 {
+            1 => t.powf(0.5f64.ln() / m.max(1e-10).ln()),
+            2 => ((PI * linear - PI / 2.0).sin() + 1.0) / 2.0,
+            3 => (1.0 - (linear - 1.0) * (linear - 1.0)).sqrt(),
+            4 => 1.0 - (1.0 - linear * linear).sqrt(),
+            5 => if t < m { 0.0 } else { 1.0 },
+            _ => linear,
+        };
+        for c in 0 .. 3 {
+            let value = segment[3 + c] * (1.0 - k) + segment[7 + c] * k;
+            colors.push((value.max(0.0).min(1.0) * 255.0 + 0.5) as u8);
+        }
+    }
+    Ok(colors)
+}
+
+/// A list of stops, either on its own or as `stops` of an object:
+/// `[{"position": 0.0, "color": "#09012f"}, {"position": 0.5, "color": [255, 170, 0]}, ...]`.
+/// The object can also set the `interpolation`, `curve`, `period`, `offset`, `density` and
+/// `transfer` of the gradient.
+fn parse_json(text: &str) -> Result<Gradient, String> {
+    let json = try!(Json::from_str(text).map_err(|err| err.to_string()));
+    let list = match json.find("stops").unwrap_or(&json).as_array() {
+        Some(list) => list.clone(),
+        None => return Err("expected a list of stops".to_string()),
+    };
+
+    let mut stops = Vec::new();
+    for stop in &list {
+        let position = try!(stop.find("position").and_then(|position| position.as_f64()).ok_or("stop without a position".to_string()));
+        let color = match stop.find("color") {
+            Some(&Json::String(ref hex)) => try!(parse_hex(hex)),
+            Some(&Json::Array(ref rgb)) if rgb.len() == 3 => {
+                let mut color = [0; 3];
+                for (c, value) in color.iter_mut().zip(rgb.iter()) {
+                    *c = match value.as_u64() {
+                        Some(value) if value < 256 => value as u8,
+                        _ => return Err("invalid colour value".to_string()),
+                    };
+                }
+                color
+            },
+            _ => return Err("stop without a colour".to_string()),
+        };
+        stops.push(Stop{ position: position, color: color });
+    }
+    if stops.is_empty() {
+        return Err("no stops".to_string());
+    }
+
+    let period = json.find("period").and_then(|period| period.as_f64()).unwrap_or(RESOLUTION as f64);
+    if period <= 0.0 {
+        return Err("period has to be positive".to_string());
+    }
+    let mut gradient = Gradient::new("", stops, period);
+    gradient.offset = json.find("offset").and_then(|offset| offset.as_f64()).unwrap_or(0.0);
+    gradient.density = json.find("density").and_then(|density| density.as_f64()).unwrap_or(1.0);
+    if gradient.density <= 0.0 {
+        return Err("density has to be positive".to_string());
+    }
+    if let Some(name) = json.find("interpolation").and_then(|name| name.as_string()) {
+        gradient.space = try!(ColorSpace::from_name(name));
+    }
+    if let Some(name) = json.find("curve").and_then(|name| name.as_string()) {
+        gradient.curve = try!(Curve::from_name(name));
+    }
+    if let Some(name) = json.find("transfer").and_then(|name| name.as_string()) {
+        gradient.transfer = try!(Transfer::from_name(name));
+    }
+    gradient.update();
+    Ok(gradient)
+}
+
+fn parse_hex(hex: &str) -> Result<[u8; 3], String> {
+    let digits = hex.trim_left_matches('#');
+    if digits.len() != 6 {
+        return Err(format!("invalid colour {}", hex));
+    }
+    let mut color = [0; 3];
+    for c in 0 .. 3 {
+        color[c] = try!(u8::from_str_radix(&digits[c * 2 .. c * 2 + 2], 16).map_err(|_| format!("invalid colour {}", hex)));
+    }
+    Ok(color)
+}
+
+#[test]
+fn test_map(){
+    assert_eq!(parse_map("0 0 0 black\n255 128 7\n\n").unwrap(), vec![0, 0, 0, 255, 128, 7]);
+    assert!(parse_map("0 0\n").is_err());
+    assert!(parse_map("0 0 300\n").is_err());
+}
+
+#[test]
+fn test_ggr(){
+    let ggr = "GIMP Gradient\nName: Test\n2\n0 0.25 0.5 0 0 0 1 1 1 1 1 0 0\n0.5 0.75 1 1 1 1 1 0 0 0 1 0 0\n";
+    let colors = parse_ggr(ggr).unwrap();
+    assert_eq!(colors.len(), RESOLUTION * 3);
+    assert_eq!(&colors[0..3], &[0, 0, 0]);
+    assert_eq!(&colors[RESOLUTION / 4 * 3 .. RESOLUTION / 4 * 3 + 3], &[128, 128, 128]);
+    assert_eq!(&colors[RESOLUTION / 2 * 3 .. RESOLUTION / 2 * 3 + 3], &[255, 255, 255]);
+    assert!(parse_ggr("GIMP Gradient\n1\n").is_err());
+}
+
+#[test]
+fn test_json(){
+    let gradient = parse_json(r##"{"stops": [{"position": 0.5, "color": [255, 255, 255]}, {"position": 0, "color": "#000000"}], "curve": "monotone"}"##).unwrap();
+    assert_eq!(gradient.stops[0], Stop{ position: 0.0, color: [0, 0, 0] });
+    assert_eq!(gradient.curve, Curve::Monotone);
+    assert_eq!(gradient.span(), RESOLUTION as f64 / 2.0);
+    assert_eq!(to_bytes(interpolate(&gradient, 0.0, 0.0)), (0, 0, 0));
+    assert_eq!(to_bytes(interpolate(&gradient, gradient.span(), 0.0)), (255, 255, 255));
+    assert!(parse_json(r#"[{"position": 0}]"#).is_err());
+    assert!(parse_json(r#"{"stops": [{"position": 0, "color": "#000000"}], "curve": "bezier"}"#).is_err());
+}
+
+#[test]
+fn test_to_json(){
+    let mut gradient = Gradient::even("", &[9, 1, 47, 255, 170, 0, 25, 7, 26, 255, 255, 255]);
+    gradient.space = ColorSpace::Lch;
+    gradient.curve = Curve::CatmullRom;
+    gradient.offset = 0.5;
+    gradient.density = 2.0;
+    gradient.transfer = Transfer::Log;
+    gradient.update();
+    assert_eq!(parse_json(&gradient.to_json()).unwrap(), gradient);
+}
+
+#[test]
+fn test_even(){
+    let gradient = Gradient::even("", &[0, 0, 0, 255, 255, 255]);
+    assert_eq!(gradient.span(), 1.0);
+    assert_eq!(to_bytes(interpolate(&gradient, 0.0, 0.0)), (0, 0, 0));
+    assert_eq!(to_bytes(interpolate(&gradient, 0.5, 0.0)), (188, 188, 188));
+    assert_eq!(to_bytes(interpolate(&gradient, 1.0, 0.0)), (255, 255, 255));
+    assert_eq!(to_bytes(interpolate(&gradient, 1.0, 0.5)), (0, 0, 0));
+    assert_eq!(to_bytes(interpolate(&gradient, 2.5, 0.0)), (188, 188, 188));
+}
+
+#[test]
+fn test_mapping(){
+    assert_eq!(Transfer::Sqrt.apply(16.0), 4.0);
+    assert_eq!(Transfer::Cbrt.apply(-27.0), -3.0);
+    assert_eq!(Transfer::Log.apply(0.0), 0.0);
+    assert_eq!(Transfer::Linear.next().next().next().next(), Transfer::Linear);
+
+    let mut gradient = Gradient::even("", &[0, 0, 0, 255, 255, 255]);
+    gradient.space = ColorSpace::Srgb;
+    gradient.update();
+    gradient.transfer = Transfer::Sqrt;
+    assert_eq!(to_bytes(interpolate(&gradient, 0.25, 0.0)), (128, 128, 128));
+    gradient.density = 2.0;
+    assert_eq!(to_bytes(interpolate(&gradient, 0.25, 0.0)), (255, 255, 255));
+    gradient.offset = 0.25;
+    assert_eq!(to_bytes(interpolate(&gradient, 0.25, 0.0)), (128, 128, 128));
+}
+
+#[test]
+fn test_spaces(){
+    for space in &[ColorSpace::Srgb, ColorSpace::LinearRgb, ColorSpace::Oklab, ColorSpace::Lch] {
+        for color in &[[0, 0, 0], [255, 255, 255], [9, 1, 47], [255, 170, 0], [127, 200, 30]] {
+            assert_eq!(space.to_rgb(space.to_space(*color)), *color);
+        }
+    }
+    let white = ColorSpace::Oklab.to_space([255, 255, 255]);
+    assert!((white[0] - 1.0).abs() < 1e-6 && white[1].abs() < 1e-6 && white[2].abs() < 1e-6);
+
+    // Linear light keeps the midpoint between red and green brighter than sRGB blending.
+    let mut gradient = Gradient::even("", &[255, 0, 0, 0, 255, 0]);
+    assert_eq!(to_bytes(interpolate(&gradient, 0.5, 0.0)), (188, 188, 0));
+    assert_eq!(to_bytes(interpolate(&gradient, 0.25, 0.0)), (225, 137, 0));
+    assert_eq!(gradient.color_at(0.25), [225, 137, 0]);
+    gradient.space = ColorSpace::Srgb;
+    gradient.update();
+    assert_eq!(gradient.blending(), Gamma::Legacy);
+    assert_eq!(to_bytes(interpolate(&gradient, 0.5, 0.0)), (128, 128, 0));
+
+    assert_eq!(to_bytes(interpolate_colors(&[0, 0, 0, 255, 255, 255], 0.5, 0.0, Gamma::Legacy)), (128, 128, 128));
+    assert_eq!(to_bytes(interpolate_colors(&[0, 0, 0, 255, 255, 255], 0.5, 0.0, Gamma::Linear)), (188, 188, 188));
+}
+
+#[test]
+fn test_curves(){
+    let colors = [0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255];
+    let mut gradient = Gradient::even("", &colors);
+    for &curve in &[Curve::CatmullRom, Curve::Monotone] {
+        gradient.curve = curve;
+        gradient.update();
+        for n in 0 .. 4 {
+            assert_eq!(to_bytes(interpolate(&gradient, n as f64, 0.0)).0, colors[n * 3]);
+        }
+    }
+
+    // Between stops of equal colour a monotone curve stays flat, and it only ever rises
+    // between a dark and a bright one.
+    let mut last = 0;
+    for n in 0 .. 100 {
+        let value = to_bytes(interpolate(&gradient, n as f64 / 50.0, 0.0)).0;
+        if n < 50 {
+            assert_eq!(value, 0);
+        }
+        assert!(value >= last);
+        last = value;
+    }
+}
@@ -0,0 +1,168 @@
+use std::f64::consts::PI;
+
+use options::*;
+use sample::*;
+use gradient::*;
+use extract::*;
+use coloring::{Rgb, Gamma, to_bytes};
+
+/// Per-pixel value a layer looks up in its gradient.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Input{
+    /// Smooth iteration count.
+    Smooth,
+    /// Whole number of iterations, in bands.
+    Iteration,
+    /// `|z|` of the last orbit point.
+    Magnitude,
+    /// Argument of the last orbit point, once round the gradient per turn.
+    Angle,
+}
+
+/// How a layer is composited onto the colours below it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Blend{
+    Normal,
+    /// Darkens, white leaves the colours below alone.
+    Multiply,
+    /// Lightens, black leaves the colours below alone.
+    Screen,
+    /// Multiply in the dark parts below, screen in the light ones, raising contrast.
+    Overlay,
+}
+
+impl Blend {
+    fn apply(&self, below: f64, layer: f64) -> f64 {
+        match *self {
+            Blend::Normal => layer,
+            Blend::Multiply => below * layer,
+            Blend::Screen => 1.0 - (1.0 - below) * (1.0 - layer),
+            Blend::Overlay => if below < 0.5 {
+                2.0 * below * layer
+            }else{
+                1.0 - 2.0 * (1.0 - below) * (1.0 - layer)
+            },
+        }
+    }
+}
+
+/// Colouring layer composited on top of the colouring, with its own input and gradient.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Layer{
+    pub input: Input,
+    pub blend: Blend,
+    pub opacity: f64,
+    pub gradient: Gradient,
+}
+
+impl Layer {
+    /// Layers from `--layers`, bottom first, separated by `;`. Palettes the layers bring in
+    /// themselves take the same `Overrides` as the others.
+    pub fn from_options(options: &Options, gradients: &[Gradient]) -> Result<Vec<Layer>, String> {
+        let extraction = try!(Extraction::from_options(options));
+        let overrides = try!(Overrides::from_options(options));
+        let mut layers: Vec<Layer> = match options.get("layers") {
+            Some(layers) => try!(layers.split(';').filter(|layer| !layer.trim().is_empty())
+                .map(|layer| Layer::parse(layer.trim(), gradients, &extraction))
+                .collect()),
+            None => Vec::new(),
+        };
+        for layer in &mut layers {
+            overrides.apply(&mut layer.gradient);
+        }
+        Ok(layers)
+    }
+
+    /// `<input>[:<blend>[:<opacity>[:<palette>]]]`, normal blending at full opacity by default.
+    /// Without a palette the layer uses the first one. The palette comes last so it can be
+    /// anything `--palette` takes.
+    pub fn parse(text: &str, gradients: &[Gradient], extraction: &Extraction) -> Result<Layer, String> {
+        let mut fields = text.splitn(4, ':');
+        let input = match fields.next().unwrap_or("") {
+            "smooth" => Input::Smooth,
+            "iteration" => Input::Iteration,
+            "magnitude" => Input::Magnitude,
+            "angle" => Input::Angle,
+            name => return Err(format!("Unknown layer input: {}", name)),
+        };
+        let blend = match fields.next().unwrap_or("normal") {
+            "normal" => Blend::Normal,
+            "multiply" => Blend::Multiply,
+            "screen" => Blend::Screen,
+            "overlay" => Blend::Overlay,
+            name => return Err(format!("Unknown blend mode: {}", name)),
+        };
+        let opacity = match fields.next() {
+            Some(opacity) => try!(opacity.parse::<f64>().map_err(|_| format!("Invalid layer opacity: {}", opacity))),
+            None => 1.0,
+        };
+        if !(opacity >= 0.0 && opacity <= 1.0) {
+            return Err(format!("Layer opacity has to be between 0 and 1: {}", opacity));
+        }
+        let gradient = match fields.next() {
+            Some(name) => match gradients.iter().find(|gradient| gradient.name == name) {
+                Some(gradient) => gradient.clone(),
+                None => try!(Gradient::named(name, extraction)),
+            },
+            None => gradients[0].clone(),
+        };
+        Ok(Layer{
+            input: input,
+            blend: blend,
+            opacity: opacity,
+            gradient: gradient,
+        })
+    }
+
+    fn value(&self, sample: &Sample) -> f64 {
+        match self.input {
+            Input::Smooth => sample.iter,
+            Input::Iteration => sample.iter.floor(),
+            Input::Magnitude => sample.z.norm(),
+            Input::Angle => (sample.z.arg() / (2.0 * PI) + 1.0).fract() * self.gradient.span(),
+        }
+    }
+
+    /// Composites this layer over `below` for `sample`, mixing colours as `gamma` says.
+    pub fn apply(&self, below: Rgb, sample: &Sample, phase: f64, gamma: Gamma) -> Rgb {
+        let (below, color) = (gamma.decode(below), gamma.decode(interpolate(&self.gradient, self.value(sample), phase)));
+        let mix = |below: f64, layer: f64| below + (self.blend.apply(below, layer) - below) * self.opacity;
+        gamma.encode((mix(below.0, color.0), mix(below.1, color.1), mix(below.2, color.2)))
+    }
+}
+
+/// Composites `layers` in order over the colouring's colour of `sample`. Pixels the colouring
+/// leaves black, like points inside the set, stay that way.
+pub fn compose(layers: &[Layer], color: Option<Rgb>, sample: &Sample, phase: f64, gamma: Gamma) -> Option<Rgb> {
+    color.map(|color| layers.iter().fold(color, |below, layer| layer.apply(below, sample, phase, gamma)))
+}
+
+#[test]
+fn test_blend(){
+    assert_eq!(Blend::Multiply.apply(0.5, 0.5), 0.25);
+    assert_eq!(Blend::Screen.apply(0.5, 0.5), 0.75);
+    assert_eq!(Blend::Overlay.apply(0.25, 0.5), 0.25);
+    assert_eq!(Blend::Overlay.apply(0.75, 0.5), 0.75);
+    for &blend in &[Blend::Normal, Blend::Multiply, Blend::Screen, Blend::Overlay] {
+        assert!(blend.apply(0.3, 0.8) >= 0.0 && blend.apply(0.3, 0.8) <= 1.0);
+    }
+}
+
+#[test]
+fn test_layers(){
+    let gradients = vec![Gradient::even("white", &[255, 255, 255])];
+    let extraction = Extraction{ method: Method::KMeans, colors: 8 };
+    let layer = Layer::parse("smooth:multiply:0.5", &gradients, &extraction).unwrap();
+    assert_eq!(layer.blend, Blend::Multiply);
+    assert_eq!(layer.gradient.name, "white");
+    assert!(Layer::parse("angle:dodge", &gradients, &extraction).is_err());
+    assert!(Layer::parse("angle:normal:2", &gradients, &extraction).is_err());
+
+    let sample = Sample::default();
+    let half = Layer::parse("smooth:normal:0.5", &gradients, &extraction).unwrap();
+    assert_eq!(compose(&[half.clone()], Some((0.0, 0.0, 0.0)), &sample, 0.0, Gamma::Legacy), Some((0.5, 0.5, 0.5)));
+    assert_eq!(compose(&[half.clone()], Some((0.0, 0.0, 0.0)), &sample, 0.0, Gamma::Linear).map(to_bytes), Some((188, 188, 188)));
+    let color = compose(&[layer, half], Some((101.0 / 255.0, 1.0 / 255.0, 201.0 / 255.0)), &sample, 0.0, Gamma::Legacy);
+    assert_eq!(color.map(to_bytes), Some((178, 128, 228)));
+    assert_eq!(compose(&[Layer::parse("smooth", &gradients, &extraction).unwrap()], None, &sample, 0.0, Gamma::Linear), None);
+}
@@ -0,0 +1,55 @@
+/// Lyapunov exponent of the logistic map `x -> r x (1 - x)` where `r` follows
+/// an A/B sequence, with A and B taken from the pixel coordinates.
+pub struct Lyapunov{
+    pub sequence: Vec<bool>,
+    pub warmup: usize,
+    pub iterations: usize,
+}
+
+impl Lyapunov {
+    pub fn new(sequence: &str, warmup: usize, iterations: usize) -> Result<Lyapunov, String>{
+        let sequence = try!(sequence.chars().map(|c| match c {
+            'A' | 'a' => Ok(false),
+            'B' | 'b' => Ok(true),
+            c => Err(format!("Invalid character in Lyapunov sequence: {}", c)),
+        }).collect::<Result<Vec<bool>, String>>());
+
+        if sequence.is_empty() {
+            return Err("Lyapunov sequence can not be empty".to_string());
+        }
+        if iterations == 0 {
+            return Err("Lyapunov fractal needs at least one iteration".to_string());
+        }
+
+        Ok(Lyapunov{
+            sequence: sequence,
+            warmup: warmup,
+            iterations: iterations,
+        })
+    }
+
+    pub fn exponent(&self, a: f64, b: f64) -> f64 {
+        let mut x = 0.5;
+        let mut r_seq = self.sequence.iter().cycle().map(|&s| if s { b } else { a });
+
+        for r in r_seq.by_ref().take(self.warmup) {
+            x = r * x * (1.0 - x);
+        }
+
+        let mut sum = 0.0;
+        for r in r_seq.take(self.iterations) {
+            sum += (r * (1.0 - 2.0 * x)).abs().ln();
+            x = r * x * (1.0 - x);
+        }
+        sum / self.iterations as f64
+    }
+}
+
+#[test]
+fn test_exponent(){
+    let lyapunov = Lyapunov::new("AB", 100, 1000).unwrap();
+    assert!(lyapunov.exponent(2.5, 2.5) < 0.0);
+    assert!(lyapunov.exponent(4.0, 4.0) > 0.0);
+    assert!(Lyapunov::new("ABC", 100, 1000).is_err());
+    assert!(Lyapunov::new("", 100, 1000).is_err());
+}
@@ -9,10 +9,17 @@
 extern crate sdl2;
 extern crate gl;
 extern crate time;
+extern crate rand;
+extern crate num_cpus;
+extern crate rustc_serialize;
+extern crate image;
 
-use std::sync::{Arc, Barrier, Mutex};
-use std::thread;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
 use std::cmp;
+use std::env;
+use std::mem;
+use std::slice;
 use std::num::Zero;
 
 mod gl_utils;
@@ -21,9 +28,37 @@ mod worker;
 use worker::*;
 mod fixed;
 use fixed::*;
+mod complex;
+mod newton;
+mod lyapunov;
+mod buddhabrot;
+use buddhabrot::*;
+mod fractal;
+use fractal::*;
+mod options;
+use options::*;
+mod picture;
+mod gradient;
+use gradient::*;
+mod procedural;
+use procedural::*;
+mod extract;
+mod layer;
+use layer::*;
+mod coloring;
+use coloring::*;
+mod sample;
+use sample::*;
+mod scheduler;
+use scheduler::*;
+mod editor;
+use editor::*;
+mod output;
+use output::*;
 
 use time::*;
 use sdl2::event::{Event, WindowEventId};
+use sdl2::keyboard::Keycode;
 
 const VERTEX_DATA: [f32; 8] = [
     -1.0, -1.0,
@@ -32,47 +67,119 @@ const VERTEX_DATA: [f32; 8] = [
      1.0,  1.0,
 ];
 
-const THREADS: usize = 16;
-const JOBS: usize = 128;
-
-
-const TEXTURE: [u8; 48] = [
-9, 1, 47,
-4, 4, 73,
-0, 7, 100,
-12, 44, 138,
-24, 82, 177,
-57, 125, 209,
-134, 181, 229,
-211, 236, 248,
-241, 233, 191,
-248, 201, 95,
-255, 170, 0,
-204, 128, 0,
-153, 87, 0,
-106, 52, 3,
-66, 30, 15,
-25, 7, 26,
-];
+/// Everything read from the command line.
+struct Settings{
+    fractal: Fractal,
+    colorings: Vec<Coloring>,
+    gradients: Vec<Gradient>,
+    /// Where N gets new palettes from.
+    generator: Generator,
+    /// Applied to generated palettes as well.
+    overrides: Overrides,
+    layers: Vec<Layer>,
+    dither: Dither,
+    gamma: Gamma,
+    antialiasings: Vec<Antialiasing>,
+    threads: usize,
+    /// Render with rectangle fill rather than computing every pixel.
+    fill: bool,
+    /// Colour cycling speed in gradient loops per second, cycling starts paused without it.
+    cycle: Option<f64>,
+}
 
-/*
-static TEXTURE: [u8; 36] = [
-255, 0, 0,
-127, 127, 127,
-255, 255, 0,
-127, 127, 127,
-0, 255, 0,
-127, 127, 127,
-0, 255, 255,
-127, 127, 127,
-0, 0, 255,
-127, 127, 127,
-255, 0, 255,
-127, 127, 127,
-];
-*/
+fn parse_args(args: &[String]) -> Result<Settings, String> {
+    let options = try!(Options::parse(args));
+    let fractal = try!(Fractal::from_options(&options));
+    let colorings = try!(Coloring::from_options(&options));
+    let gradients = try!(Gradient::from_options(&options));
+    let generator = try!(Generator::from_options(&options));
+    let overrides = try!(Overrides::from_options(&options));
+    let layers = try!(Layer::from_options(&options, &gradients));
+    let dither = try!(Dither::from_options(&options));
+    let gamma = try!(Gamma::from_options(&options));
+    let antialiasings = try!(Antialiasing::from_options(&options));
+    let threads = try!(options.get_or("threads", num_cpus::get()));
+    if threads == 0 {
+        return Err("At least one thread is needed".to_string());
+    }
+    let fill = match options.get("fill").unwrap_or("scan") {
+        "scan" => false,
+        "rectangles" => true,
+        name => return Err(format!("Unknown fill mode: {}", name)),
+    };
+    let cycle = match options.get("cycle") {
+        Some(speed) => Some(try!(speed.parse::<f64>().map_err(|_| format!("Invalid value for --cycle: {}", speed)))),
+        None => None,
+    };
+    try!(options.finish());
+    Ok(Settings{
+        fractal: fractal,
+        colorings: colorings,
+        gradients: gradients,
+        generator: generator,
+        overrides: overrides,
+        layers: layers,
+        dither: dither,
+        gamma: gamma,
+        antialiasings: antialiasings,
+        threads: threads,
+        fill: fill,
+        cycle: cycle,
+    })
+}
+
+/// Changes a gradient that colouring jobs may still share, through a copy.
+fn edit<F: FnOnce(&mut Gradient)>(gradient: &mut Arc<Gradient>, change: F) {
+    let mut edited = (**gradient).clone();
+    change(&mut edited);
+    *gradient = Arc::new(edited);
+}
+
+/// Adds a mouse motion to the drag carried over from earlier ones, the view moves by the whole
+/// pixels of it.
+fn add_drag(drag: (f64, f64), xrel: i32, yrel: i32, window_size: (u32, u32)) -> (f64, f64) {
+    (drag.0 + xrel as f64, drag.1 + yrel as f64 * window_size.1 as f64 / window_size.0 as f64)
+}
+
+/// Whether an event arriving during a render pass makes the pass obsolete. Drags only do once
+/// they add up to a whole pixel, see `add_drag`.
+fn interrupts(event: &Event) -> bool {
+    match *event {
+        Event::Quit{..} | Event::MouseWheel{..} => true,
+        Event::Window{ win_event_id: WindowEventId::SizeChanged, .. } => true,
+        Event::KeyDown{ keycode: Some(Keycode::C), .. } |
+        Event::KeyDown{ keycode: Some(Keycode::A), .. } |
+        Event::KeyDown{ keycode: Some(Keycode::F), .. } => true,
+        _ => false,
+    }
+}
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Settings{ fractal, colorings, gradients, generator, overrides, layers, dither, gamma, antialiasings, threads, mut fill, cycle } = match parse_args(&args) {
+        Ok(settings) => settings,
+        Err(err) => {
+            println!("{}\n\n{}", err, USAGE);
+            return;
+        },
+    };
+    // Workers share these with the main thread.
+    let fractal = Arc::new(fractal);
+    let colorings: Vec<Arc<Coloring>> = colorings.into_iter().map(Arc::new).collect();
+    let mut gradients: Vec<Arc<Gradient>> = gradients.into_iter().map(Arc::new).collect();
+    let layers = Arc::new(layers);
+    let dither = Arc::new(dither);
+    let mut coloring = 0;
+    let mut gradient = 0;
+    let mut antialiasing = 0;
+    let mut layered = true;
+    let mut exporting = false;
+    let no_layers = Arc::new(Vec::new());
+    let mut cycle_speed = cycle.unwrap_or(0.1);
+    let mut cycling = cycle.is_some();
+    let mut phase = 0.0;
+    let mut last_frame = SteadyTime::now();
+
     let ctx = sdl2::init().unwrap_or_else(|err| panic!("Unable to initialize sdl2: {}", err));
     let video = ctx.video().unwrap_or_else(|err| panic!("Unable to initialize sld2 video: {}", err));
     video.gl_load_library_default().unwrap_or_else(|err| panic!("Unable to load gl library: {}", err));
@@ -99,27 +206,67 @@ fn main() {
 
     let mut window_size = window.size();
     let mut scale = (f8_120::from(1.5), f8_120::from(1.5) * f8_120::from(window_size.1 as f32 / window_size.0 as f32));
-    let mut center = (f8_120::from(0.5), f8_120::from(0.0));
+    let default_center = fractal.default_center();
+    let mut center = (f8_120::from(default_center.0), f8_120::from(default_center.1));
 
     let mut buffers = generate_buffers(window_size);
     let _tex = generate_texture(window_size);
 
-    let barrier = Arc::new(Barrier::new(THREADS + 1));
-    let jobs = Arc::new(Mutex::new(Vec::<Job>::new()));
-
-    for _ in 0 .. THREADS {
-        let jobs_clone = jobs.clone();
-        let barrier_clone = barrier.clone();
-        thread::spawn(move || {
-            let mut worker = Worker::new(barrier_clone);
-            worker.run(jobs_clone);
-        });
-    }
+    let density = Arc::new(Mutex::new(Density::new(window_size.0 as usize, window_size.1 as usize)));
+    let mut density_view = None;
+    let mut frame = 0u32;
+    // Frames accumulated with the current view.
+    let mut accumulated = 0;
+    let mut samples = Vec::<Sample>::new();
+    let mut subsamples = Vec::<Sample>::new();
+    let mut samples_view = None;
+    let mut samples_passes = 0;
+    // Part of the view a cancelled pan left intact, the rest of it still has to be computed.
+    let mut intact = None;
+    let mut drag = (0.0f64, 0.0f64);
+    let mut pan = (0isize, 0isize);
+    let mut redraw = true;
+    let mut paused = false;
+    let progress = Arc::new(Progress::new());
+    let mut scheduler = Scheduler::new(threads, progress.clone());
+    let mut queued = Vec::new();
+    let mut editor = Editor::new();
 
     'main : loop {
-        for event in events.as_mut().unwrap().poll_iter() {
+        // Frames are only rendered when something changed, or while the image is still being
+        // refined. Otherwise the loop sleeps until the next event, and so do the workers.
+        let animating = cycling || match *fractal {
+            Fractal::Buddhabrot(_) => accumulated < DENSITY_FRAMES,
+            _ => samples_passes < PASSES.len() || intact.is_some(),
+        };
+        let pump = events.as_mut().unwrap();
+        let mut pending: Vec<Event> = mem::replace(&mut queued, Vec::new());
+        if pending.is_empty() && (paused || !(redraw || animating)) {
+            pending.push(pump.wait_event());
+        }
+        pending.extend(pump.poll_iter());
+
+        for event in pending {
+            if editor.open {
+                // Edits go to a copy, workers may still hold on to the gradient being shown.
+                let mut edited = (*gradients[gradient]).clone();
+                if editor.handle(&event, &mut edited, (window_size.0 as usize, window_size.1 as usize)) {
+                    gradients[gradient] = Arc::new(edited);
+                    redraw = true;
+                    continue;
+                }
+            }
             match event {
                 Event::Quit{..} => break 'main,
+                Event::Window{ win_event_id: WindowEventId::Minimized, .. } |
+                Event::Window{ win_event_id: WindowEventId::Hidden, .. } => {
+                    paused = true;
+                    continue;
+                },
+                Event::Window{ win_event_id: WindowEventId::Restored, .. } |
+                Event::Window{ win_event_id: WindowEventId::Maximized, .. } |
+                Event::Window{ win_event_id: WindowEventId::Shown, .. } => paused = false,
+                Event::Window{ win_event_id: WindowEventId::Exposed, .. } => {},
                 Event::Window{  win_event_id: WindowEventId::SizeChanged, data1: x, data2: y, .. } => unsafe{
                     window_size = (x as u32, y as u32);
                     gl::Viewport(0,0,x,y);
@@ -131,14 +278,103 @@ fn main() {
                     scale.0 = scale.0 * f8_120::from(1.0 - ( x + y ) as f32 * 0.2);
                     scale.1 = scale.0 * f8_120::from(window_size.1 as f32 / window_size.0 as f32);
                 },
+                Event::KeyDown{ keycode: Some(Keycode::C), .. } => {
+                    coloring = (coloring + 1) % colorings.len();
+                    println!("Coloring: {}", colorings[coloring].name());
+                },
+                Event::KeyDown{ keycode: Some(Keycode::G), .. } => {
+                    gradient = (gradient + 1) % gradients.len();
+                    println!("Palette: {}", gradients[gradient].name);
+                },
+                Event::KeyDown{ keycode: Some(Keycode::T), .. } => edit(&mut gradients[gradient], |gradient| {
+                    gradient.transfer = gradient.transfer.next();
+                    println!("Transfer: {}", gradient.transfer.name());
+                }),
+                Event::KeyDown{ keycode: Some(Keycode::RightBracket), .. } => edit(&mut gradients[gradient], |gradient| {
+                    gradient.density *= 1.25;
+                    println!("Density: {:.3}", gradient.density);
+                }),
+                Event::KeyDown{ keycode: Some(Keycode::LeftBracket), .. } => edit(&mut gradients[gradient], |gradient| {
+                    gradient.density /= 1.25;
+                    println!("Density: {:.3}", gradient.density);
+                }),
+                Event::KeyDown{ keycode: Some(Keycode::Equals), .. } => edit(&mut gradients[gradient], |gradient| {
+                    gradient.offset = (gradient.offset + 0.05) % 1.0;
+                    println!("Offset: {:.2}", gradient.offset);
+                }),
+                Event::KeyDown{ keycode: Some(Keycode::Minus), .. } => edit(&mut gradients[gradient], |gradient| {
+                    gradient.offset = (gradient.offset + 0.95) % 1.0;
+                    println!("Offset: {:.2}", gradient.offset);
+                }),
+                Event::KeyDown{ keycode: Some(Keycode::E), .. } => {
+                    editor.open = !editor.open;
+                    println!("Gradient editor: {}", if editor.open { "on" } else { "off" });
+                },
+                Event::KeyDown{ keycode: Some(Keycode::N), .. } => {
+                    // Generated palettes are kept, G goes back to the earlier ones.
+                    let mut generated = generator.generate(rand::random::<u16>() as u32);
+                    overrides.apply(&mut generated);
+                    gradients.push(Arc::new(generated));
+                    gradient = gradients.len() - 1;
+                    println!("Palette: {}", gradients[gradient].name);
+                },
+                Event::KeyDown{ keycode: Some(Keycode::L), .. } => {
+                    layered = !layered;
+                    println!("Layers: {}", if layered { "on" } else { "off" });
+                },
+                Event::KeyDown{ keycode: Some(Keycode::P), .. } => exporting = true,
+                Event::KeyDown{ keycode: Some(Keycode::A), .. } => {
+                    antialiasing = (antialiasing + 1) % antialiasings.len();
+                    println!("Antialiasing: {:?}", antialiasings[antialiasing]);
+                },
+                Event::KeyDown{ keycode: Some(Keycode::Space), .. } => {
+                    cycling = !cycling;
+                    last_frame = SteadyTime::now();
+                },
+                Event::KeyDown{ keycode: Some(Keycode::R), .. } => cycle_speed = -cycle_speed,
+                Event::KeyDown{ keycode: Some(Keycode::Period), .. } => {
+                    cycle_speed *= 1.5;
+                    println!("Cycle speed: {:.3}", cycle_speed);
+                },
+                Event::KeyDown{ keycode: Some(Keycode::Comma), .. } => {
+                    cycle_speed /= 1.5;
+                    println!("Cycle speed: {:.3}", cycle_speed);
+                },
+                Event::KeyDown{ keycode: Some(Keycode::F), .. } => {
+                    fill = !fill;
+                    println!("Rectangle fill: {}", if fill { "on" } else { "off" });
+                },
                 Event::MouseMotion{ mousestate, xrel, yrel, ..} if mousestate.left() => {
-                    center.0 = center.0 + f8_120::from(xrel as f32 / window_size.0 as f32 * 2.0) * scale.0;
-                    center.1 = center.1 - f8_120::from(yrel as f32 / window_size.0 as f32 * 2.0) * scale.1;
+                    // The view only moves by whole pixels so the samples can be shifted along,
+                    // the rest of the drag is carried over to the next motion.
+                    drag = add_drag(drag, xrel, yrel, window_size);
+                    let shift = (drag.0.trunc(), drag.1.trunc());
+                    drag = (drag.0 - shift.0, drag.1 - shift.1);
+                    center.0 = center.0 + f8_120::from(shift.0 * 2.0 / window_size.0 as f64) * scale.0;
+                    center.1 = center.1 - f8_120::from(shift.1 * 2.0 / window_size.1 as f64) * scale.1;
+                    pan = (pan.0 + shift.0 as isize, pan.1 + shift.1 as isize);
                 },
                 _   => continue
             }
+            redraw = true;
         }
 
+        if paused || !(redraw || animating) {
+            continue;
+        }
+        redraw = false;
+
+        // Colour cycling only rotates the gradient, the samples stay as they are.
+        let now = SteadyTime::now();
+        if cycling {
+            let elapsed = (now - last_frame).num_microseconds().unwrap_or(0) as f64 / 1_000_000.0;
+            phase = (phase + cycle_speed * elapsed) % 1.0;
+            if phase < 0.0 {
+                phase += 1.0;
+            }
+        }
+        last_frame = now;
+
         fps += 1;
         if SteadyTime::now() - last >= Duration::seconds(1) {
             println!("{} FPS", fps);
@@ -147,27 +383,209 @@ fn main() {
         }
 
         unsafe{
-            barrier.wait();
             let pbo = map_buffer((window_size.0 * window_size.1 * 4) as usize);
-            {
-                let mut jobs = jobs.lock().unwrap();
+            let (pbo_ptr, pbo_len) = (pbo.as_mut_ptr(), pbo.len());
+            // Exports get the same colours at 16 bits per channel, without dithering.
+            let mut image = if exporting { Some(vec![0u16; (window_size.0 * window_size.1 * 3) as usize]) } else { None };
+            exporting = false;
+            // Keep reading input while jobs are running, anything that changes the view
+            // abandons them instead of waiting for them to finish.
+            let mut moved = drag;
+            let mut wait = |scheduler: &mut Scheduler| {
+                while scheduler.pending() > 0 {
+                    while scheduler.poll().is_some() {}
+                    if scheduler.pending() == 0 {
+                        break;
+                    }
+                    if let Some(event) = pump.wait_event_timeout(5) {
+                        let interrupt = match event {
+                            Event::MouseMotion{ ref mousestate, xrel, yrel, .. } if mousestate.left() => {
+                                moved = add_drag(moved, xrel, yrel, window_size);
+                                moved.0.abs() >= 1.0 || moved.1.abs() >= 1.0
+                            },
+                            _ => interrupts(&event),
+                        };
+                        if interrupt && !editor.dragging() {
+                            progress.generation.fetch_add(1, Ordering::SeqCst);
+                        }
+                        queued.push(event);
+                    }
+                }
+            };
+            if let Fractal::Buddhabrot(ref buddhabrot) = *fractal {
+                // Keep accumulating orbits while the view stays the same, so the image gets
+                // less noisy every frame, until it has settled.
+                let view = (window_size, scale, center);
+                if density_view != Some(view) {
+                    *density.lock().unwrap() = Density::new(window_size.0 as usize, window_size.1 as usize);
+                    density_view = Some(view);
+                    accumulated = 0;
+                }
+                if accumulated < DENSITY_FRAMES {
+                    let generation = progress.generation.load(Ordering::SeqCst);
+                    let mut tasks = Vec::new();
+                    for n in 0 .. scheduler.threads() {
+                        tasks.push(Task::Accumulate(DensityJob::new(
+                            density.clone(),
+                            window_size.0 as usize,
+                            window_size.1 as usize,
+                            scale,
+                            center,
+                            fractal.clone(),
+                            frame.wrapping_mul(scheduler.threads() as u32).wrapping_add(n as u32 + 1),
+                            generation,
+                        )));
+                    }
+                    scheduler.dispatch(tasks);
+                    wait(&mut scheduler);
+                    frame = frame.wrapping_add(1);
+                    accumulated += 1;
+                }
+                let density_lock = density.lock().unwrap();
+                density_lock.write_colors(buddhabrot.mode, &gradients[gradient], phase, &mut Target::Rgba8(pbo), &dither);
+                if let Some(ref mut image) = image {
+                    density_lock.write_colors(buddhabrot.mode, &gradients[gradient], phase, &mut Target::Rgb16(&mut image[..]), &dither);
+                }
+            }else{
                 let window_size = (window_size.0 as usize, window_size.1 as usize);
-                let job_heigth = cmp::max(window_size.1 / JOBS, 1);
-                let mut row = 0;
-                let chunk_size = job_heigth * 4 * window_size.0;
-                for slice in pbo.chunks_mut( chunk_size ) {
-                    jobs.push(Job::new(
-                        slice,
-                        row,
-                        window_size.0,
-                        window_size.1,
-                        scale,
-                        center,
-                    ));
-                    row += job_heigth;
+                let band_heigth = cmp::max(window_size.1 / (scheduler.threads() * 4), 1);
+                // Subsamples go into one buffer, reused until the size or the mode changes.
+                let pixels = window_size.0 * window_size.1;
+                if samples.len() != pixels {
+                    samples = vec![Sample::default(); pixels];
                 }
+                if subsamples.len() != pixels * antialiasings[antialiasing].subsamples() {
+                    subsamples = vec![Sample::default(); pixels * antialiasings[antialiasing].subsamples()];
+                }
+
+                // Samples only have to be computed again when the view changed or the new
+                // colouring needs something else out of the orbit, recolouring reuses them.
+                // A new view is computed one progressive pass per frame, so a coarse image
+                // shows up right away and sharpens in place. Panning a finished view shifts
+                // the samples and only computes the strips that came into view.
+                let current = &colorings[coloring];
+                let view = (window_size, scale, antialiasing, fill, if current.observes_orbit() { Some(coloring) } else { None });
+                if samples_view != Some(view) {
+                    samples_view = Some(view);
+                    samples_passes = 0;
+                    pan = (0, 0);
+                }
+                let mut kept = None;
+                if pan != (0, 0) {
+                    if samples_passes == PASSES.len() && pan.0.abs() < window_size.0 as isize && pan.1.abs() < window_size.1 as isize {
+                        let shifted = shift_samples(&mut samples, &mut subsamples, window_size.0, window_size.1, pan);
+                        kept = Some(match intact.take() {
+                            Some(intact) => carry_region(intact, shifted, window_size.0, window_size.1, pan),
+                            None => shifted,
+                        });
+                    }else{
+                        samples_passes = 0;
+                    }
+                    pan = (0, 0);
+                }else if samples_passes == PASSES.len() {
+                    kept = intact.take();
+                }
+                if samples_passes < PASSES.len() {
+                    intact = None;
+                }
+                let pass = if samples_passes < PASSES.len() { Some(samples_passes) } else { None };
+                if pass.is_some() {
+                    kept = Some(((0, 0), (0, 0)));
+                }
+                if let Some(kept) = kept {
+                    let (rows, cols) = kept;
+                    let generation = progress.generation.load(Ordering::SeqCst);
+                    let shared = SharedSamples::new(&mut samples[..], &mut subsamples[..]);
+                    let tiles: Vec<Tile> = Tile::split(window_size.0, window_size.1).into_iter().filter(|tile| {
+                        !(rows.0 <= tile.y && rows.1 >= tile.y + tile.heigth && cols.0 <= tile.x && cols.1 >= tile.x + tile.width)
+                    }).collect();
+                    let render = RenderSettings{
+                        samples: shared,
+                        width: window_size.0,
+                        heigth: window_size.1,
+                        scale: scale,
+                        center: center,
+                        fractal: fractal.clone(),
+                        coloring: current.clone(),
+                        antialiasing: antialiasings[antialiasing],
+                        pass: pass,
+                        kept: kept,
+                        generation: generation,
+                        fill: fill,
+                    };
+                    // Adaptive antialiasing compares every pixel with its neighbours, which
+                    // takes the whole pass to be finished first.
+                    let mut stages = vec![tiles.iter().map(|&tile| Task::Render(Job::new(&render, tile))).collect::<Vec<_>>()];
+                    if let (Antialiasing::Adaptive(_), true) = (antialiasings[antialiasing], pass.map_or(true, |pass| pass + 1 == PASSES.len())) {
+                        stages.push(tiles.iter().map(|&tile| Task::Refine(Job::new(&render, tile))).collect());
+                    }
+                    for tasks in stages {
+                        if progress.generation.load(Ordering::SeqCst) != generation {
+                            break;
+                        }
+                        scheduler.dispatch(tasks);
+                        wait(&mut scheduler);
+                    }
+
+                    if progress.generation.load(Ordering::SeqCst) != generation {
+                        // A cancelled pass is computed again, the passes before it are intact.
+                        // Only what a cancelled pan kept is intact, the next pan shifts it along
+                        // with the samples and computes everything outside of it.
+                        if pass.is_none() {
+                            intact = Some(kept);
+                        }
+                    }else if pass.is_some() {
+                        samples_passes += 1;
+                    }
+                }
+                let block = PASSES[cmp::max(samples_passes, 1) - 1];
+
+                let histogram = match **current {
+                    Coloring::Histogram => Some(Arc::new(Histogram::new(samples.iter().enumerate().filter(|&(n, _)| {
+                        n % window_size.0 % block == 0 && n / window_size.0 % block == 0
+                    }).map(|(_, sample)| {
+                        if sample.is_inside() { MAX_ITERATIONS as f64 } else { sample.iter }
+                    }), MAX_ITERATIONS))),
+                    _ => None,
+                };
+                let colors = ColorSettings{
+                    samples: mem::transmute(&samples[..]),
+                    subsamples: mem::transmute(&subsamples[..]),
+                    width: window_size.0,
+                    block: block,
+                    fractal: fractal.clone(),
+                    coloring: current.clone(),
+                    histogram: histogram,
+                    gradient: gradients[gradient].clone(),
+                    layers: if layered { layers.clone() } else { no_layers.clone() },
+                    dither: dither.clone(),
+                    gamma: gamma,
+                    phase: phase,
+                };
+                let exported = image.as_mut().map(|image| Target::Rgb16(mem::transmute(&mut image[..])));
+                let mut tasks = Vec::new();
+                for target in Some(Target::Rgba8(pbo)).into_iter().chain(exported) {
+                    let mut row = 0;
+                    for band in target.split(band_heigth * window_size.0) {
+                        tasks.push(Task::Colorize(ColorJob::new(&colors, band, row)));
+                        row += band_heigth;
+                    }
+                }
+                scheduler.dispatch(tasks);
+                scheduler.finish();
+            }
+            if let Some(image) = image {
+                let path = format!("mandelrust-{}.ppm", get_time().sec);
+                match save_ppm16(&path, window_size.0 as usize, window_size.1 as usize, &image) {
+                    Ok(()) => println!("Saved image to {}", path),
+                    Err(err) => println!("{}", err),
+                }
+            }
+            if editor.open {
+                // The colouring jobs are done with the buffer, the editor is drawn on top.
+                let pbo = slice::from_raw_parts_mut(pbo_ptr, pbo_len);
+                editor.draw(&gradients[gradient], pbo, window_size.0 as usize, window_size.1 as usize);
             }
-            barrier.wait();
             buffers = swap_buffer(buffers, window_size);
             gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
             gl::Flush();
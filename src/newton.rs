@@ -0,0 +1,163 @@
+use complex::*;
+
+/// Polynomial with complex coefficients, highest degree first.
+#[derive(Clone, Debug)]
+pub struct Polynomial{
+    pub coeffs: Vec<Complex>,
+}
+
+impl Polynomial {
+    pub fn new(coeffs: Vec<Complex>) -> Polynomial{
+        let first = coeffs.iter().position(|c| c.norm_sqr() != 0.0).unwrap_or(coeffs.len());
+        Polynomial{
+            coeffs: coeffs[first..].to_vec(),
+        }
+    }
+
+    pub fn from_roots(roots: &[Complex]) -> Polynomial{
+        let mut coeffs = vec![Complex::new(1.0, 0.0)];
+        for &root in roots {
+            coeffs.push(Complex::new(0.0, 0.0));
+            for n in (1..coeffs.len()).rev() {
+                coeffs[n] = coeffs[n] - coeffs[n - 1] * root;
+            }
+        }
+        Polynomial::new(coeffs)
+    }
+
+    pub fn degree(&self) -> usize {
+        if self.coeffs.is_empty() { 0 } else { self.coeffs.len() - 1 }
+    }
+
+    /// Evaluates the polynomial and its derivative at `z` using Horner's scheme.
+    pub fn eval(&self, z: Complex) -> (Complex, Complex) {
+        let mut p = Complex::new(0.0, 0.0);
+        let mut dp = Complex::new(0.0, 0.0);
+        for &c in self.coeffs.iter() {
+            dp = dp * z + p;
+            p = p * z + c;
+        }
+        (p, dp)
+    }
+
+    /// Finds all roots at once with the Durand-Kerner method.
+    pub fn roots(&self) -> Vec<Complex> {
+        let degree = self.degree();
+        if degree == 0 {
+            return Vec::new();
+        }
+
+        let lead = self.coeffs[0];
+        let monic = Polynomial{ coeffs: self.coeffs.iter().map(|&c| c / lead).collect() };
+        let seed = Complex::new(0.4, 0.9);
+        let mut roots = Vec::with_capacity(degree);
+        let mut guess = Complex::new(1.0, 0.0);
+        for _ in 0 .. degree {
+            roots.push(guess);
+            guess = guess * seed;
+        }
+
+        for _ in 0 .. 500 {
+            let mut delta = 0.0;
+            for n in 0 .. degree {
+                let mut denom = Complex::new(1.0, 0.0);
+                for m in 0 .. degree {
+                    if m != n {
+                        denom = denom * (roots[n] - roots[m]);
+                    }
+                }
+                let step = monic.eval(roots[n]).0 / denom;
+                if step.is_finite() {
+                    roots[n] = roots[n] - step;
+                    delta += step.norm_sqr();
+                }
+            }
+            if delta < 1e-28 {
+                break;
+            }
+        }
+        roots
+    }
+}
+
+pub struct Newton{
+    pub poly: Polynomial,
+    pub roots: Vec<Complex>,
+    pub max_iter: i32,
+    pub tolerance: f64,
+}
+
+impl Newton {
+    pub fn new(poly: Polynomial, max_iter: i32) -> Newton{
+        Newton{
+            roots: poly.roots(),
+            poly: poly,
+            max_iter: max_iter,
+            tolerance: 1e-6,
+        }
+    }
+
+    /// Iterates Newton's method from `z`. Returns the index of the root it converged to
+    /// and the smooth iteration count, or `None` if it did not converge.
+    pub fn solve(&self, mut z: Complex) -> Option<(usize, f64)> {
+        let tol_ln = self.tolerance.ln();
+        let mut last_dist = None;
+        for i in 0 .. self.max_iter {
+            let (n, dist) = self.nearest_root(z);
+            if dist < self.tolerance {
+                let fract = match last_dist {
+                    Some(last) if last > dist => ((tol_ln - f64::ln(last)) / (dist.ln() - f64::ln(last))).max(0.0).min(1.0),
+                    _ => 1.0,
+                };
+                return Some((n, (i as f64 - 1.0 + fract).max(0.0)));
+            }
+            last_dist = Some(dist);
+
+            let (p, dp) = self.poly.eval(z);
+            let step = p / dp;
+            if !step.is_finite() {
+                return None;
+            }
+            z = z - step;
+        }
+        None
+    }
+
+    fn nearest_root(&self, z: Complex) -> (usize, f64) {
+        let mut best = (0, ::std::f64::INFINITY);
+        for (n, &root) in self.roots.iter().enumerate() {
+            let dist = (z - root).norm();
+            if dist < best.1 {
+                best = (n, dist);
+            }
+        }
+        best
+    }
+}
+
+#[test]
+fn test_roots(){
+    let expected = vec![Complex::new(1.0, 0.0), Complex::new(-0.5, 0.75f64.sqrt()), Complex::new(-0.5, -0.75f64.sqrt())];
+    let poly = Polynomial::from_roots(&expected);
+    assert_eq!(poly.degree(), 3);
+
+    let c = poly.coeffs.clone();
+    assert!((c[0] - Complex::new(1.0, 0.0)).norm() < 1e-12);
+    assert!(c[1].norm() < 1e-12 && c[2].norm() < 1e-12);
+    assert!((c[3] - Complex::new(-1.0, 0.0)).norm() < 1e-12);
+
+    let roots = poly.roots();
+    for root in expected {
+        assert!(roots.iter().any(|&r| (r - root).norm() < 1e-9));
+    }
+}
+
+#[test]
+fn test_solve(){
+    let newton = Newton::new(Polynomial::new(vec![Complex::new(1.0, 0.0), Complex::new(0.0, 0.0), Complex::new(-1.0, 0.0)]), 100);
+    let (n, i) = newton.solve(Complex::new(3.0, 0.1)).unwrap();
+    assert!((newton.roots[n] - Complex::new(1.0, 0.0)).norm() < 1e-9);
+    assert!(i > 0.0);
+    let (n, _) = newton.solve(Complex::new(-3.0, 0.1)).unwrap();
+    assert!((newton.roots[n] - Complex::new(-1.0, 0.0)).norm() < 1e-9);
+}
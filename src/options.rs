@@ -0,0 +1,84 @@
+use std::cell::RefCell;
+use std::str::FromStr;
+
+use complex::*;
+
+/// Command line arguments: an optional name followed by `--key value` pairs.
+/// Options are looked up by whoever needs them, `finish` then reports those nobody asked for.
+pub struct Options{
+    name: Option<String>,
+    pairs: Vec<(String, String)>,
+    used: RefCell<Vec<bool>>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut name = None;
+        let mut pairs = Vec::new();
+        let mut iter = args.iter().peekable();
+        if iter.peek().map_or(false, |arg| !arg.starts_with("--")) {
+            name = iter.next().cloned();
+        }
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                return Err(format!("Unexpected argument: {}", arg));
+            }
+            match iter.next() {
+                Some(value) => pairs.push((arg[2..].to_string(), value.clone())),
+                None => return Err(format!("Missing value for {}", arg)),
+            }
+        }
+        Ok(Options{
+            name: name,
+            used: RefCell::new(vec![false; pairs.len()]),
+            pairs: pairs,
+        })
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|s| &s[..])
+    }
+
+    pub fn finish(&self) -> Result<(), String> {
+        let used = self.used.borrow();
+        match self.pairs.iter().zip(used.iter()).find(|&(_, &used)| !used) {
+            Some((&(ref key, _), _)) => Err(format!("Unknown option: --{}", key)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match self.pairs.iter().position(|&(ref k, _)| k == key) {
+            Some(n) => {
+                self.used.borrow_mut()[n] = true;
+                Some(&self.pairs[n].1[..])
+            },
+            None => None,
+        }
+    }
+
+    pub fn get_or<T: FromStr>(&self, key: &str, default: T) -> Result<T, String> {
+        match self.get(key) {
+            Some(value) => value.parse().map_err(|_| format!("Invalid value for --{}: {}", key, value)),
+            None => Ok(default),
+        }
+    }
+}
+
+pub fn parse_list(list: &str) -> Result<Vec<Complex>, String> {
+    list.split(',').map(|s| s.parse()).collect()
+}
+
+#[test]
+fn test_options(){
+    let args: Vec<String> = vec!["newton", "--roots", "-1,1", "--foo", "bar"].into_iter().map(|s| s.to_string()).collect();
+    let options = Options::parse(&args).unwrap();
+    assert_eq!(options.name(), Some("newton"));
+    assert_eq!(options.get("roots"), Some("-1,1"));
+    assert_eq!(options.get_or("iterations", 10), Ok(10));
+    assert!(options.finish().is_err());
+    assert_eq!(options.get("foo"), Some("bar"));
+    assert!(options.finish().is_ok());
+
+    assert!(Options::parse(&["--roots".to_string()]).is_err());
+}
@@ -0,0 +1,209 @@
+use std::cmp;
+use std::fs;
+use std::io::Write;
+
+use options::*;
+use coloring::{Rgb, Gamma};
+
+/// Side of the ordered dithering matrix.
+const BAYER_SIZE: usize = 8;
+/// Side of the blue noise tile, it repeats over the frame.
+const BLUE_NOISE_SIZE: usize = 64;
+/// Spread of the filter blue noise is generated with, in pixels.
+const BLUE_NOISE_SIGMA: f64 = 1.5;
+
+/// Offsets added to colours before they are rounded to 8 bits. Neighbouring pixels round up
+/// and down in turn, so smooth gradients stay smooth instead of breaking into bands.
+pub struct Dither{
+    size: usize,
+    /// Row by row, from -0.5 to 0.5 of an 8-bit step.
+    thresholds: Vec<f64>,
+}
+
+impl Dither {
+    /// `--gamma legacy` truncates like older versions did unless `--dither` is given.
+    pub fn from_options(options: &Options) -> Result<Dither, String> {
+        if options.get("dither").is_none() && try!(Gamma::from_options(options)) == Gamma::Legacy {
+            return Ok(Dither::truncate());
+        }
+        match options.get("dither").unwrap_or("ordered") {
+            "off" => Ok(Dither::off()),
+            "ordered" => Ok(Dither::ordered()),
+            "blue-noise" => Ok(Dither::blue_noise()),
+            name => Err(format!("Unknown dithering: {}", name)),
+        }
+    }
+
+    pub fn off() -> Dither {
+        Dither{
+            size: 1,
+            thresholds: vec![0.0],
+        }
+    }
+
+    /// Rounds every value down, the way colours were converted to 8 bits before dithering.
+    pub fn truncate() -> Dither {
+        Dither{
+            size: 1,
+            thresholds: vec![-0.5],
+        }
+    }
+
+    /// Bayer matrix, a regular pattern that is cheap and never clumps.
+    pub fn ordered() -> Dither {
+        let bits = BAYER_SIZE.trailing_zeros();
+        let mut ranks = Vec::with_capacity(BAYER_SIZE * BAYER_SIZE);
+        for y in 0 .. BAYER_SIZE {
+            for x in 0 .. BAYER_SIZE {
+                // Interleaves the bits of x ^ y and y, lowest bit first.
+                let mut rank = 0;
+                for bit in 0 .. bits {
+                    rank = (rank << 2) | (((x ^ y) >> bit & 1) << 1) | (y >> bit & 1);
+                }
+                ranks.push(rank);
+            }
+        }
+        Dither::from_ranks(BAYER_SIZE, &ranks)
+    }
+
+    /// Blue noise from a void and cluster pass: every pixel in turn goes where the pattern so
+    /// far leaves the biggest gap. Looks like film grain without the Bayer cross hatching.
+    pub fn blue_noise() -> Dither {
+        let size = BLUE_NOISE_SIZE;
+        let n = size * size;
+        let mut kernel = vec![0.0; n];
+        for dy in 0 .. size {
+            for dx in 0 .. size {
+                // Distances wrap around, the tile has to repeat seamlessly.
+                let (x, y) = (cmp::min(dx, size - dx) as f64, cmp::min(dy, size - dy) as f64);
+                kernel[dy * size + dx] = (-(x * x + y * y) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp();
+            }
+        }
+
+        let mut energy = vec![0.0; n];
+        let mut ranks = vec![0; n];
+        let mut taken = vec![false; n];
+        for rank in 0 .. n {
+            let mut void = None;
+            for p in 0 .. n {
+                if !taken[p] && void.map_or(true, |void: usize| energy[p] < energy[void]) {
+                    void = Some(p);
+                }
+            }
+            let p = void.unwrap();
+            taken[p] = true;
+            ranks[p] = rank;
+            let (px, py) = (p % size, p / size);
+            for q in 0 .. n {
+                let (dx, dy) = ((q % size + size - px) % size, (q / size + size - py) % size);
+                energy[q] += kernel[dy * size + dx];
+            }
+        }
+        Dither::from_ranks(size, &ranks)
+    }
+
+    fn from_ranks(size: usize, ranks: &[usize]) -> Dither {
+        let n = ranks.len() as f64;
+        Dither{
+            size: size,
+            thresholds: ranks.iter().map(|&rank| (rank as f64 + 0.5) / n - 0.5).collect(),
+        }
+    }
+
+    /// 8-bit value of a channel in 0..1 at pixel `x`, `y`.
+    pub fn quantize(&self, value: f64, x: usize, y: usize) -> u8 {
+        let threshold = self.thresholds[(y % self.size) * self.size + x % self.size];
+        (value.max(0.0).min(1.0) * 255.0 + 0.5 + threshold).max(0.0).min(255.0) as u8
+    }
+}
+
+/// Pixels the colouring pass writes to.
+pub enum Target<'a>{
+    /// RGBA bytes of the frame in the window, dithered.
+    Rgba8(&'a mut [u8]),
+    /// 16 bits per RGB channel, for exports.
+    Rgb16(&'a mut [u16]),
+}
+
+impl<'a> Target<'a> {
+    pub fn pixels(&self) -> usize {
+        match *self {
+            Target::Rgba8(ref data) => data.len() / 4,
+            Target::Rgb16(ref data) => data.len() / 3,
+        }
+    }
+
+    /// Splits into parts of `pixels` pixels, the last one may be shorter.
+    pub fn split(self, pixels: usize) -> Vec<Target<'a>> {
+        match self {
+            Target::Rgba8(data) => data.chunks_mut(pixels * 4).map(Target::Rgba8).collect(),
+            Target::Rgb16(data) => data.chunks_mut(pixels * 3).map(Target::Rgb16).collect(),
+        }
+    }
+
+    /// Writes pixel `n`, which is at `x`, `y` in the frame. `None` is black.
+    pub fn put(&mut self, n: usize, color: Option<Rgb>, x: usize, y: usize, dither: &Dither) {
+        let color = color.unwrap_or((0.0, 0.0, 0.0));
+        match *self {
+            Target::Rgba8(ref mut data) => {
+                let pixel = &mut data[n * 4 .. n * 4 + 4];
+                pixel[0] = dither.quantize(color.0, x, y);
+                pixel[1] = dither.quantize(color.1, x, y);
+                pixel[2] = dither.quantize(color.2, x, y);
+                pixel[3] = 255;
+            },
+            Target::Rgb16(ref mut data) => {
+                let wide = |value: f64| (value.max(0.0).min(1.0) * 65535.0 + 0.5) as u16;
+                data[n * 3] = wide(color.0);
+                data[n * 3 + 1] = wide(color.1);
+                data[n * 3 + 2] = wide(color.2);
+            },
+        }
+    }
+}
+
+/// Saves 16-bit RGB pixels as a binary PPM. Rows are given bottom first, like the frame.
+pub fn save_ppm16(path: &str, width: usize, heigth: usize, data: &[u16]) -> Result<(), String> {
+    fs::File::create(path).and_then(|mut file| file.write_all(&encode_ppm16(width, heigth, data)))
+        .map_err(|err| format!("Unable to save image {}: {}", path, err))
+}
+
+fn encode_ppm16(width: usize, heigth: usize, data: &[u16]) -> Vec<u8> {
+    let mut bytes = format!("P6\n{} {}\n65535\n", width, heigth).into_bytes();
+    for row in data.chunks(width * 3).rev() {
+        for &value in row {
+            bytes.push((value >> 8) as u8);
+            bytes.push(value as u8);
+        }
+    }
+    bytes
+}
+
+#[test]
+fn test_dither(){
+    for dither in &[Dither::ordered(), Dither::blue_noise()] {
+        let mut ranks: Vec<f64> = dither.thresholds.clone();
+        ranks.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let n = ranks.len() as f64;
+        for (rank, &threshold) in ranks.iter().enumerate() {
+            assert_eq!(threshold, (rank as f64 + 0.5) / n - 0.5);
+        }
+
+        // A flat colour between two 8-bit values comes out as a mix of both, averaging to it.
+        let value = 100.25 / 255.0;
+        let size = dither.size;
+        let sum = (0 .. size * size).map(|n| dither.quantize(value, n % size, n / size) as f64).fold(0.0, |a, b| a + b);
+        assert!((sum / (size * size) as f64 - 100.25).abs() < 0.01);
+    }
+    assert_eq!(Dither::ordered().thresholds[0], -0.5 + 0.5 / 64.0);
+    assert_eq!(Dither::off().quantize(0.5, 3, 7), 128);
+    assert_eq!(Dither::truncate().quantize(0.5, 3, 7), 127);
+    assert_eq!(Dither::truncate().quantize(1.0, 3, 7), 255);
+}
+
+#[test]
+fn test_ppm16(){
+    let bytes = encode_ppm16(1, 2, &[0x0102, 0, 0xffff, 1, 2, 3]);
+    assert_eq!(&bytes[.. 15], b"P6\n1 2\n65535\n\x00\x01");
+    assert_eq!(&bytes[15 ..], &[0, 2, 0, 3, 1, 2, 0, 0, 255, 255]);
+}
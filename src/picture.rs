@@ -0,0 +1,143 @@
+use std::cmp;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use image;
+
+/// RGBA image loaded from disk.
+pub struct Picture{
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// Loads PPM files directly and everything else (PNG, JPEG, ...) through the `image` crate.
+    pub fn load(path: &str) -> Result<Picture, String> {
+        let is_ppm = Path::new(path).extension().map_or(false, |ext| ext == "ppm" || ext == "pnm");
+        if is_ppm {
+            let mut file = try!(fs::File::open(path).map_err(|err| format!("Unable to load image {}: {}", path, err)));
+            let mut bytes = Vec::new();
+            try!(file.read_to_end(&mut bytes).map_err(|err| format!("Unable to load image {}: {}", path, err)));
+            parse_ppm(&bytes).map_err(|err| format!("Unable to load image {}: {}", path, err))
+        }else{
+            let img = try!(image::open(&Path::new(path)).map_err(|err| format!("Unable to load image {}: {}", path, err)));
+            let rgba = img.to_rgba();
+            let (width, height) = rgba.dimensions();
+            Ok(Picture{
+                width: width as usize,
+                height: height as usize,
+                data: rgba.into_raw(),
+            })
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let n = (y * self.width + x) * 4;
+        [self.data[n], self.data[n + 1], self.data[n + 2], self.data[n + 3]]
+    }
+
+    /// Bilinearly filtered colour at `u`, `v` in 0..1 texture coordinates, `None` outside the picture.
+    pub fn sample(&self, u: f64, v: f64) -> Option<[f64; 4]> {
+        if !(u >= 0.0 && v >= 0.0 && u < 1.0 && v < 1.0) || self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        let x = (u * self.width as f64 - 0.5).max(0.0);
+        let y = (v * self.height as f64 - 0.5).max(0.0);
+        let (x0, y0) = (x as usize, y as usize);
+        let (x1, y1) = (cmp::min(x0 + 1, self.width - 1), cmp::min(y0 + 1, self.height - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let (p00, p10, p01, p11) = (self.pixel(x0, y0), self.pixel(x1, y0), self.pixel(x0, y1), self.pixel(x1, y1));
+        let mut out = [0.0; 4];
+        for c in 0 .. 4 {
+            let top = p00[c] as f64 * (1.0 - fx) + p10[c] as f64 * fx;
+            let bottom = p01[c] as f64 * (1.0 - fx) + p11[c] as f64 * fx;
+            out[c] = top * (1.0 - fy) + bottom * fy;
+        }
+        Some(out)
+    }
+}
+
+/// Parses binary (P6) and ASCII (P3) pixmaps with 8 or 16 bits per channel.
+fn parse_ppm(bytes: &[u8]) -> Result<Picture, String> {
+    let mut pos = 0;
+    let mut header = Vec::new();
+    while header.len() < 4 {
+        while pos < bytes.len() && (bytes[pos] as char).is_whitespace() {
+            pos += 1;
+        }
+        if pos < bytes.len() && bytes[pos] == b'#' {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while pos < bytes.len() && !(bytes[pos] as char).is_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("truncated header".to_string());
+        }
+        header.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+    }
+    pos += 1;
+
+    let binary = match &header[0][..] {
+        "P6" => true,
+        "P3" => false,
+        magic => return Err(format!("unsupported format {}", magic)),
+    };
+    let parse = |s: &str| s.parse::<usize>().map_err(|_| format!("invalid header value {}", s));
+    let width = try!(parse(&header[1]));
+    let height = try!(parse(&header[2]));
+    let maxval = try!(parse(&header[3]));
+    if maxval == 0 || maxval > 65535 {
+        return Err(format!("invalid maximum value {}", maxval));
+    }
+
+    let count = width * height * 3;
+    let values: Vec<usize> = if binary {
+        let size = if maxval > 255 { 2 } else { 1 };
+        if bytes.len() < pos + count * size {
+            return Err("truncated pixel data".to_string());
+        }
+        bytes[pos .. pos + count * size].chunks(size).map(|c| if size == 2 { (c[0] as usize) << 8 | c[1] as usize } else { c[0] as usize }).collect()
+    }else{
+        let text = String::from_utf8_lossy(&bytes[cmp::min(pos, bytes.len())..]).into_owned();
+        try!(text.split_whitespace().take(count).map(|s| parse(s)).collect::<Result<Vec<usize>, String>>())
+    };
+    if values.len() < count {
+        return Err("truncated pixel data".to_string());
+    }
+
+    let mut data = Vec::with_capacity(width * height * 4);
+    for rgb in values.chunks(3) {
+        for &v in rgb {
+            data.push((cmp::min(v, maxval) * 255 / maxval) as u8);
+        }
+        data.push(255);
+    }
+    Ok(Picture{
+        width: width,
+        height: height,
+        data: data,
+    })
+}
+
+#[test]
+fn test_ppm(){
+    let binary = b"P6\n# comment\n2 1\n255\n\x00\x80\xff\x10\x20\x30";
+    let picture = parse_ppm(binary).unwrap();
+    assert_eq!((picture.width, picture.height), (2, 1));
+    assert_eq!(picture.data, vec![0, 128, 255, 255, 16, 32, 48, 255]);
+
+    let ascii = b"P3 1 2 65535\n65535 0 32768\n0 65535 0\n";
+    let picture = parse_ppm(ascii).unwrap();
+    assert_eq!(picture.data, vec![255, 0, 127, 255, 0, 255, 0, 255]);
+
+    assert!(parse_ppm(b"P6 2 2 255\n\x00").is_err());
+}
@@ -0,0 +1,190 @@
+use std::f64::consts::PI;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use options::*;
+use gradient::*;
+
+/// Stops cosine palettes are sampled to.
+const SAMPLES: usize = 32;
+
+/// `interpolate` input one loop through a cosine palette takes, the same as for the default
+/// gradient.
+const COSINE_PERIOD: f64 = 16.0;
+
+/// Palette of the a + b·cos(2π(c·t + d)) family, with one such curve for each of red, green
+/// and blue. Integer frequencies `c` make it loop seamlessly.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cosine{
+    pub a: [f64; 3],
+    pub b: [f64; 3],
+    pub c: [f64; 3],
+    pub d: [f64; 3],
+}
+
+impl Cosine {
+    /// Twelve comma separated numbers: the red, green and blue of `a`, then of `b`, `c` and `d`.
+    pub fn parse(text: &str) -> Result<Cosine, String> {
+        let values = try!(text.split(',').map(|value| value.trim().parse::<f64>()).collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| format!("Invalid cosine palette: {}", text)));
+        if values.len() != 12 {
+            return Err(format!("A cosine palette needs 12 numbers: {}", text));
+        }
+        let channels = |n: usize| [values[n * 3], values[n * 3 + 1], values[n * 3 + 2]];
+        Ok(Cosine{
+            a: channels(0),
+            b: channels(1),
+            c: channels(2),
+            d: channels(3),
+        })
+    }
+
+    /// Random palette that loops and stays within the colour range.
+    pub fn random<R: Rng>(rng: &mut R) -> Cosine {
+        let mut cosine = Cosine{ a: [0.0; 3], b: [0.0; 3], c: [0.0; 3], d: [0.0; 3] };
+        for n in 0 .. 3 {
+            cosine.a[n] = rng.gen_range(0.3, 0.7);
+            cosine.b[n] = rng.gen_range(0.2, 1.0) * cosine.a[n].min(1.0 - cosine.a[n]);
+            cosine.c[n] = *rng.choose(&[1.0, 1.0, 2.0]).unwrap();
+            cosine.d[n] = rng.gen_range(0.0, 1.0);
+        }
+        cosine
+    }
+
+    pub fn color(&self, t: f64) -> [u8; 3] {
+        let mut color = [0; 3];
+        for n in 0 .. 3 {
+            let value = self.a[n] + self.b[n] * (2.0 * PI * (self.c[n] * t + self.d[n])).cos();
+            color[n] = (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+        }
+        color
+    }
+
+    pub fn gradient(&self, name: &str) -> Gradient {
+        let stops = (0 .. SAMPLES).map(|n| n as f64 / SAMPLES as f64).map(|t| Stop{
+            position: t,
+            color: self.color(t),
+        }).collect();
+        let mut gradient = Gradient::new(name, stops, COSINE_PERIOD);
+        gradient.curve = Curve::CatmullRom;
+        gradient.update();
+        gradient
+    }
+}
+
+/// Source of random palettes, each one reproducible from its seed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Generator{
+    /// A few colours on a colour scheme around a random hue, lightness rising and falling over
+    /// the loop.
+    Harmonious,
+    /// Random `Cosine` palettes.
+    Cosine,
+}
+
+impl Generator {
+    pub fn from_options(options: &Options) -> Result<Generator, String> {
+        Generator::from_name(options.get("generator").unwrap_or("harmonious"))
+    }
+
+    pub fn from_name(name: &str) -> Result<Generator, String> {
+        match name {
+            "harmonious" => Ok(Generator::Harmonious),
+            "cosine" => Ok(Generator::Cosine),
+            name => Err(format!("Unknown palette generator: {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Generator::Harmonious => "harmonious",
+            Generator::Cosine => "cosine",
+        }
+    }
+
+    /// Palette for `seed`, named `<generator>-<seed>` so `--palette` can load it again.
+    pub fn generate(&self, seed: u32) -> Gradient {
+        let mut rng = XorShiftRng::from_seed([mix(seed) | 1, 0x193a6754, 0xa8a7d469, 0x97830e05]);
+        // The first numbers out of a fresh generator follow the seed closely.
+        for _ in 0 .. 8 {
+            rng.next_u32();
+        }
+        let name = format!("{}-{}", self.name(), seed);
+        match *self {
+            Generator::Harmonious => harmonious(&mut rng, &name),
+            Generator::Cosine => Cosine::random(&mut rng).gradient(&name),
+        }
+    }
+}
+
+/// Generated palette for names like `harmonious-<seed>`, `cosine-<seed>` or
+/// `cosine:<a>,<b>,<c>,<d>`. `None` if `name` isn't one.
+pub fn parse(name: &str) -> Option<Result<Gradient, String>> {
+    if name.starts_with("cosine:") {
+        return Some(Cosine::parse(&name["cosine:".len() ..]).map(|cosine| cosine.gradient("cosine")));
+    }
+    let dash = match name.rfind('-') {
+        Some(dash) => dash,
+        None => return None,
+    };
+    match (Generator::from_name(&name[.. dash]), name[dash + 1 ..].parse::<u32>()) {
+        (Ok(generator), Ok(seed)) => Some(Ok(generator.generate(seed))),
+        _ => None,
+    }
+}
+
+fn harmonious<R: Rng>(rng: &mut R, name: &str) -> Gradient {
+    // Hue offsets of analogous, complementary, triadic and split complementary schemes.
+    let schemes: [&[f64]; 4] = [&[0.0, 30.0, -30.0], &[0.0, 180.0], &[0.0, 120.0, 240.0], &[0.0, 150.0, 210.0]];
+    let scheme = *rng.choose(&schemes).unwrap();
+    let hue = rng.gen_range(0.0, 360.0);
+    let count = rng.gen_range(4, 8);
+
+    let stops = (0 .. count).map(|n| {
+        let t = n as f64 / count as f64;
+        let lightness = 0.25 + 0.6 * (0.5 - 0.5 * (2.0 * PI * t).cos()) + rng.gen_range(-0.05, 0.05);
+        let chroma = rng.gen_range(0.05, 0.16);
+        let hue = (hue + scheme[n % scheme.len()] + rng.gen_range(-10.0, 10.0)).to_radians();
+        Stop{
+            position: t,
+            color: ColorSpace::Lch.to_rgb([lightness, chroma, hue]),
+        }
+    }).collect();
+    let mut gradient = Gradient::new(name, stops, count as f64);
+    gradient.space = ColorSpace::Oklab;
+    gradient.curve = Curve::CatmullRom;
+    gradient.update();
+    gradient
+}
+
+/// Spreads nearby seeds all over the range (the MurmurHash3 finalizer).
+fn mix(seed: u32) -> u32 {
+    let mut h = seed;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+#[test]
+fn test_cosine(){
+    let cosine = Cosine::parse("0.5,0.5,0.5, 0.5,0.5,0.5, 1,1,1, 0,0,0.5").unwrap();
+    assert_eq!(cosine.color(0.0), [255, 255, 0]);
+    assert_eq!(cosine.color(0.5), [0, 0, 255]);
+    assert_eq!(cosine.gradient("").stops.len(), SAMPLES);
+    assert!(Cosine::parse("0.5,0.5").is_err());
+}
+
+#[test]
+fn test_generate(){
+    for &generator in &[Generator::Harmonious, Generator::Cosine] {
+        assert_eq!(generator.generate(42), generator.generate(42));
+        assert!(generator.generate(42).stops != generator.generate(43).stops);
+        let name = generator.generate(7).name;
+        assert_eq!(parse(&name).unwrap().unwrap(), generator.generate(7));
+    }
+    assert!(parse("default").is_none());
+    assert!(parse("sunset-2").is_none());
+    assert!(parse("cosine:1,2").unwrap().is_err());
+}
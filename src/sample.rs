@@ -0,0 +1,220 @@
+use std::cmp;
+
+use complex::*;
+use options::*;
+
+/// The point did not escape (or, for Newton fractals, did not converge).
+pub const INSIDE: u8 = 1;
+/// Set on the first subsample of a pixel when its subsamples were computed, otherwise the
+/// sample alone stands for the pixel.
+pub const REFINED: u8 = 2;
+
+/// Block sizes of the progressive passes. Every pass computes the pixels in the corners of
+/// its blocks that earlier passes skipped, until the last one has computed all of them.
+pub const PASSES: [usize; 3] = [4, 2, 1];
+
+/// Whether pass `pass` is the one computing the pixel at `col`, `row`.
+pub fn computed_in(pass: usize, col: usize, row: usize) -> bool {
+    let block = PASSES[pass];
+    col % block == 0 && row % block == 0 && (pass == 0 || col % PASSES[pass - 1] != 0 || row % PASSES[pass - 1] != 0)
+}
+
+/// Rectangle of a view given as a range of rows and a range of columns, the pixels in both of
+/// them belong to it.
+pub type Region = ((usize, usize), (usize, usize));
+
+/// Whether the pixel at `col`, `row` lies outside `kept` and has to be computed.
+pub fn exposed(kept: Region, col: usize, row: usize) -> bool {
+    let (rows, cols) = kept;
+    !(row >= rows.0 && row < rows.1 && col >= cols.0 && col < cols.1)
+}
+
+/// Moves the samples of a `width` x `heigth` view, and the subsamples of refined pixels, along
+/// with a pan by `offset` pixels, so the sample at `col`, `row` ends up at `col + offset.0`,
+/// `row - offset.1`. `subsamples` holds the same number of them for every pixel, one after the
+/// other. Returns the part of the view the samples were kept for, the rest came into view and
+/// has to be computed again.
+pub fn shift_samples(samples: &mut [Sample], subsamples: &mut [Sample], width: usize, heigth: usize, offset: (isize, isize)) -> Region {
+    let (dx, dy) = offset;
+    let per_pixel = subsamples.len() / samples.len();
+    let (w, h) = (width as isize, heigth as isize);
+    let source = |n: usize| {
+        let (col, row) = ((n % width) as isize - dx, (n / width) as isize + dy);
+        if col >= 0 && col < w && row >= 0 && row < h { Some((row * w + col) as usize) } else { None }
+    };
+
+    // Copy in the direction the samples move, so none is overwritten before it was copied.
+    let pixels = width * heigth;
+    let forward = dy * w - dx > 0;
+    for i in 0 .. pixels {
+        let n = if forward { i } else { pixels - 1 - i };
+        if let Some(from) = source(n) {
+            samples[n] = samples[from];
+            for k in 0 .. per_pixel {
+                subsamples[n * per_pixel + k] = subsamples[from * per_pixel + k];
+            }
+        }
+    }
+
+    let rows = if dy > 0 { (0, cmp::max(h - dy, 0) as usize) } else { (cmp::min(-dy, h) as usize, heigth) };
+    let cols = if dx > 0 { (cmp::min(dx, w) as usize, width) } else { (0, cmp::max(w + dx, 0) as usize) };
+    (rows, cols)
+}
+
+/// Moves `intact`, the part of the view a cancelled pass left intact, along with a
+/// `shift_samples` by `offset` and keeps only where it overlaps `kept`, the part that shift
+/// kept. Rows and columns are cut down separately, so the result is a rectangle again.
+pub fn carry_region(intact: Region, kept: Region, width: usize, heigth: usize, offset: (isize, isize)) -> Region {
+    let shift = |range: (usize, usize), by: isize, size: usize| {
+        let clamp = |n: isize| cmp::min(cmp::max(n, 0), size as isize) as usize;
+        (clamp(range.0 as isize + by), clamp(range.1 as isize + by))
+    };
+    let overlap = |a: (usize, usize), b: (usize, usize)| {
+        let start = cmp::max(a.0, b.0);
+        (start, cmp::max(cmp::min(a.1, b.1), start))
+    };
+    (overlap(shift(intact.0, -offset.1, heigth), kept.0), overlap(shift(intact.1, offset.0, width), kept.1))
+}
+
+/// Everything the colouring pass needs to know about one pixel.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sample{
+    /// Smooth iteration count.
+    pub iter: f64,
+    /// Last value of the orbit.
+    pub z: Complex,
+    /// Distance estimate to the boundary of the set.
+    pub de: f64,
+    /// Value of the orbit statistic the current colouring asked for: trap distance, orbit
+    /// average, Lyapunov exponent or Newton root index.
+    pub stat: f64,
+    /// Premultiplied picture trap colour, transparent if the orbit missed the picture.
+    pub hit: [u8; 4],
+    pub flags: u8,
+}
+
+impl Sample {
+    pub fn is_inside(&self) -> bool {
+        self.flags & INSIDE != 0
+    }
+
+    /// Whether a rectangle fill may stand in one sample for the other: both inside with the
+    /// same statistic, or escaped with the same iteration count and picture trap colour.
+    pub fn same_region(&self, other: &Sample) -> bool {
+        if self.is_inside() || other.is_inside() {
+            self.is_inside() && other.is_inside() && self.stat == other.stat && self.hit == other.hit
+        }else{
+            self.iter == other.iter && self.hit == other.hit
+        }
+    }
+
+    /// Whether two neighbouring samples are far enough apart to be worth supersampling.
+    pub fn differs(&self, other: &Sample) -> bool {
+        self.flags & INSIDE != other.flags & INSIDE || (self.iter - other.iter).abs() > 0.5 || (self.stat - other.stat).abs() > 0.5
+    }
+}
+
+/// How many samples make up one pixel and where they are taken.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Antialiasing{
+    Off,
+    /// Regular n x n grid of subsamples.
+    Grid(usize),
+    /// One subsample at a random spot in every cell of an n x n grid.
+    Jittered(usize),
+    /// A single sample, refined to an n x n grid where it differs from its neighbours.
+    Adaptive(usize),
+}
+
+impl Antialiasing {
+    /// Subsamples kept for every pixel, none without antialiasing.
+    pub fn subsamples(&self) -> usize {
+        match *self {
+            Antialiasing::Off => 0,
+            Antialiasing::Grid(n) | Antialiasing::Jittered(n) | Antialiasing::Adaptive(n) => n * n,
+        }
+    }
+
+    /// All modes, the one selected on the command line first, so the rest can be cycled
+    /// through at runtime.
+    pub fn from_options(options: &Options) -> Result<Vec<Antialiasing>, String> {
+        // Every subsample is kept for recolouring, larger grids take too much memory.
+        let n = try!(options.get_or("supersampling", 3));
+        if n < 2 || n > 4 {
+            return Err("Supersampling grid size has to be between 2 and 4".to_string());
+        }
+        let mut modes = vec![Antialiasing::Off, Antialiasing::Grid(n), Antialiasing::Jittered(n), Antialiasing::Adaptive(n)];
+        let first = match options.get("antialiasing").unwrap_or("off") {
+            "off" => 0,
+            "grid" => 1,
+            "jittered" => 2,
+            "adaptive" => 3,
+            name => return Err(format!("Unknown antialiasing mode: {}", name)),
+        };
+        let first = modes.remove(first);
+        modes.insert(0, first);
+        Ok(modes)
+    }
+}
+
+#[test]
+fn test_passes(){
+    for row in 0 .. 16 {
+        for col in 0 .. 16 {
+            let passes: Vec<usize> = (0 .. PASSES.len()).filter(|&pass| computed_in(pass, col, row)).collect();
+            assert_eq!(passes.len(), 1);
+            // Whatever pass computes a pixel, the corner of its block in that pass is already done.
+            let block = PASSES[passes[0]];
+            assert!((0 .. passes[0] + 1).any(|pass| computed_in(pass, col - col % block, row - row % block)));
+        }
+    }
+}
+
+#[test]
+fn test_shift_samples(){
+    let sample = |n: usize| Sample{ iter: n as f64, ..Sample::default() };
+    let mut samples: Vec<Sample> = (0 .. 12).map(sample).collect();
+    let mut subsamples: Vec<Sample> = (0 .. 24).map(|k| sample(k / 2)).collect();
+    // 4 x 3 view panned one pixel right and one down.
+    let kept = shift_samples(&mut samples, &mut subsamples, 4, 3, (1, -1));
+    assert_eq!(kept, ((1, 3), (1, 4)));
+    assert!(exposed(kept, 0, 2) && exposed(kept, 3, 0) && !exposed(kept, 1, 1));
+    let iters: Vec<f64> = samples.iter().map(|sample| sample.iter).collect();
+    assert_eq!(&iters[5..8], &[0.0, 1.0, 2.0]);
+    assert_eq!(&iters[9..12], &[4.0, 5.0, 6.0]);
+    assert_eq!(subsamples[12].iter, 1.0);
+    assert_eq!(subsamples[13].iter, 1.0);
+    assert_eq!(subsamples[15].iter, 2.0);
+
+    let mut samples: Vec<Sample> = (0 .. 12).map(sample).collect();
+    let kept = shift_samples(&mut samples, &mut subsamples, 4, 3, (-2, 1));
+    assert_eq!(kept, ((0, 2), (0, 2)));
+    let iters: Vec<f64> = samples.iter().map(|sample| sample.iter).collect();
+    assert_eq!(&iters[0..2], &[6.0, 7.0]);
+    assert_eq!(&iters[4..6], &[10.0, 11.0]);
+}
+
+#[test]
+fn test_carry_region(){
+    // Top rows left unfinished, then the view moves two more pixels down.
+    assert_eq!(carry_region(((3, 6), (0, 8)), ((2, 6), (0, 8)), 8, 6, (0, -2)), ((5, 6), (0, 8)));
+    // Right columns left unfinished, and a diagonal pan pushes them partly out of view. The
+    // columns the pan brings in on the left are not carried.
+    assert_eq!(carry_region(((0, 6), (0, 5)), ((0, 5), (1, 8)), 8, 6, (1, 1)), ((0, 5), (1, 6)));
+    assert_eq!(carry_region(((2, 6), (0, 8)), ((0, 6), (0, 6)), 8, 6, (-2, 0)), ((2, 6), (0, 6)));
+    // Nothing carried over stays that way.
+    assert_eq!(carry_region(((0, 0), (0, 0)), ((0, 5), (1, 8)), 8, 6, (1, 1)), ((0, 0), (1, 1)));
+}
+
+#[test]
+fn test_same_region(){
+    let inside = Sample{ iter: 3.0, flags: INSIDE, ..Sample::default() };
+    let escaped = Sample{ iter: 5.2, ..Sample::default() };
+    assert!(inside.same_region(&Sample{ iter: 7.0, ..inside }));
+    assert!(!inside.same_region(&Sample{ stat: 1.0, ..inside }));
+    assert!(!inside.same_region(&escaped));
+    assert!(escaped.same_region(&escaped));
+    assert!(escaped.same_region(&Sample{ z: Complex::new(1.0, 0.0), de: 0.5, ..escaped }));
+    assert!(!escaped.same_region(&Sample{ iter: 5.9, ..escaped }));
+    assert!(!escaped.same_region(&Sample{ hit: [255, 0, 0, 255], ..escaped }));
+}
@@ -0,0 +1,176 @@
+use std::cmp;
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use worker::*;
+
+/// Side of the square tiles a view is split into for rendering.
+pub const TILE_SIZE: usize = 64;
+
+/// Rectangle of pixels computed by one `Render` job.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tile{
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub heigth: usize,
+}
+
+impl Tile {
+    /// Splits a `width` x `heigth` view into tiles, row by row.
+    pub fn split(width: usize, heigth: usize) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y in (0 .. (heigth + TILE_SIZE - 1) / TILE_SIZE).map(|n| n * TILE_SIZE) {
+            for x in (0 .. (width + TILE_SIZE - 1) / TILE_SIZE).map(|n| n * TILE_SIZE) {
+                tiles.push(Tile{
+                    x: x,
+                    y: y,
+                    width: cmp::min(TILE_SIZE, width - x),
+                    heigth: cmp::min(TILE_SIZE, heigth - y),
+                });
+            }
+        }
+        tiles
+    }
+}
+
+/// Tasks handed out in one go. Workers claim the next one by bumping `next`, so nobody waits
+/// for a lock to get work and fast workers just end up taking more of it.
+struct Batch{
+    tasks: Vec<Mutex<Option<Task>>>,
+    next: AtomicUsize,
+}
+
+struct State{
+    batch: Option<Arc<Batch>>,
+    epoch: usize,
+}
+
+struct Shared{
+    state: Mutex<State>,
+    wakeup: Condvar,
+}
+
+/// Pool of worker threads. Idle workers sleep until the next `dispatch`, every finished task
+/// is reported back on its own.
+pub struct Scheduler{
+    shared: Arc<Shared>,
+    done: Receiver<usize>,
+    threads: usize,
+    pending: usize,
+}
+
+impl Scheduler {
+    pub fn new(threads: usize, progress: Arc<Progress>) -> Scheduler {
+        let shared = Arc::new(Shared{
+            state: Mutex::new(State{
+                batch: None,
+                epoch: 0,
+            }),
+            wakeup: Condvar::new(),
+        });
+        let (done_tx, done) = channel();
+
+        for _ in 0 .. threads {
+            let shared = shared.clone();
+            let done_tx = done_tx.clone();
+            let progress = progress.clone();
+            thread::spawn(move || {
+                let mut worker = Worker::new(progress);
+                let mut epoch = 0;
+                loop {
+                    let batch = {
+                        let mut state = shared.state.lock().unwrap();
+                        while state.epoch == epoch {
+                            state = shared.wakeup.wait(state).unwrap();
+                        }
+                        epoch = state.epoch;
+                        match state.batch {
+                            Some(ref batch) => batch.clone(),
+                            None => continue,
+                        }
+                    };
+
+                    loop {
+                        let n = batch.next.fetch_add(1, Ordering::SeqCst);
+                        if n >= batch.tasks.len() {
+                            break;
+                        }
+                        if let Some(task) = batch.tasks[n].lock().unwrap().take() {
+                            worker.run(task);
+                        }
+                        if done_tx.send(n).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+
+        Scheduler{
+            shared: shared,
+            done: done,
+            threads: threads,
+            pending: 0,
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Tasks dispatched and not finished yet.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Hands `tasks` to the workers and returns right away. Tasks still running from an
+    /// earlier dispatch are waited for first.
+    pub fn dispatch(&mut self, tasks: Vec<Task>) {
+        self.finish();
+        if tasks.is_empty() {
+            return;
+        }
+        self.pending = tasks.len();
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.batch = Some(Arc::new(Batch{
+            tasks: tasks.into_iter().map(|task| Mutex::new(Some(task))).collect(),
+            next: AtomicUsize::new(0),
+        }));
+        state.epoch += 1;
+        self.shared.wakeup.notify_all();
+    }
+
+    /// Index of a task that finished since the last call, without blocking.
+    pub fn poll(&mut self) -> Option<usize> {
+        if self.pending == 0 {
+            return None;
+        }
+        match self.done.try_recv() {
+            Ok(n) => {
+                self.pending -= 1;
+                Some(n)
+            },
+            Err(_) => None,
+        }
+    }
+
+    /// Blocks until every dispatched task is finished.
+    pub fn finish(&mut self) {
+        while self.pending > 0 {
+            self.done.recv().unwrap();
+            self.pending -= 1;
+        }
+    }
+}
+
+#[test]
+fn test_split(){
+    let tiles = Tile::split(150, 64);
+    assert_eq!(tiles.len(), 3);
+    assert_eq!(tiles[2], Tile{ x: 128, y: 0, width: 22, heigth: 64 });
+    assert_eq!(tiles.iter().map(|tile| tile.width * tile.heigth).fold(0, |a, b| a + b), 150 * 64);
+}
@@ -1,98 +1,485 @@
-extern crate time;
-use time::*;
-use std::f32::consts::PI as PI;
-use std::sync::{Arc, Barrier, Mutex};
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::ops::*;
 
 use fixed::*;
-use super::TEXTURE;
+use complex::*;
+use fractal::*;
+use newton::*;
+use lyapunov::*;
+use buddhabrot::*;
+use coloring::*;
+use sample::*;
+use gradient::*;
+use layer::*;
+use output::*;
+use scheduler::Tile;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+pub enum Task {
+    /// Computes one sample per pixel of the view.
+    Render(Job),
+    /// Scatters orbits over the view and merges the hit counts into a shared `Density`.
+    Accumulate(DensityJob),
+    /// Subsamples where the samples of a finished `Render` pass differ, for adaptive
+    /// antialiasing.
+    Refine(Job),
+    /// Colours samples a previous `Render` pass left behind.
+    Colorize(ColorJob),
+}
+
+/// Samples of the whole view and the subsamples of its refined pixels, shared by the `Render`
+/// jobs of a pass. Every job only touches the pixels of its own tile.
+#[derive(Clone, Copy)]
+pub struct SharedSamples {
+    ptr: *mut Sample,
+    subsamples: *mut Sample,
+    len: usize,
+    /// Subsamples of every pixel, they are stored one pixel after the other.
+    per_pixel: usize,
+}
+
+unsafe impl Send for SharedSamples {}
+
+impl SharedSamples {
+    /// `samples`, one per pixel, and `subsamples`, the same number for every pixel, have to
+    /// stay alive and in place until the jobs using them are finished.
+    pub unsafe fn new(samples: &mut [Sample], subsamples: &mut [Sample]) -> SharedSamples {
+        let per_pixel = subsamples.len() / samples.len();
+        assert_eq!(subsamples.len(), samples.len() * per_pixel);
+        SharedSamples{
+            ptr: samples.as_mut_ptr(),
+            subsamples: subsamples.as_mut_ptr(),
+            len: samples.len(),
+            per_pixel: per_pixel,
+        }
+    }
+
+    unsafe fn slice(&self, start: usize, len: usize) -> (&'static mut [Sample], &'static mut [Sample]) {
+        assert!(start + len <= self.len);
+        let per_pixel = self.per_pixel;
+        (slice::from_raw_parts_mut(self.ptr.offset(start as isize), len),
+         slice::from_raw_parts_mut(self.subsamples.offset((start * per_pixel) as isize), len * per_pixel))
+    }
+
+    unsafe fn at(&self, index: usize) -> Sample {
+        assert!(index < self.len);
+        *self.ptr.offset(index as isize)
+    }
+
+    unsafe fn set(&self, index: usize, sample: Sample) {
+        assert!(index < self.len);
+        *self.ptr.offset(index as isize) = sample;
+        if let Some(first) = self.subsamples(index).first_mut() {
+            first.flags &= !REFINED;
+        }
+    }
+
+    /// Subsamples of the pixel at `index`.
+    unsafe fn subsamples(&self, index: usize) -> &'static mut [Sample] {
+        assert!(index < self.len);
+        slice::from_raw_parts_mut(self.subsamples.offset((index * self.per_pixel) as isize), self.per_pixel)
+    }
+}
+
+/// Everything the `Render` jobs of a pass have in common, only their tiles differ.
+#[derive(Clone)]
+pub struct RenderSettings {
+    pub samples: SharedSamples,
+    pub width: usize,
+    pub heigth: usize,
+    pub scale: (f8_120, f8_120),
+    pub center: (f8_120, f8_120),
+    pub fractal: Arc<Fractal>,
+    pub coloring: Arc<Coloring>,
+    pub antialiasing: Antialiasing,
+    /// Index into `PASSES`, only the pixels this pass is responsible for get computed.
+    /// `None` computes every pixel, as the last pass would after the earlier ones.
+    pub pass: Option<usize>,
+    /// Part of the view whose samples are kept, only the pixels outside of it get computed.
+    pub kept: Region,
+    /// `Progress::generation` at the time the jobs were pushed.
+    pub generation: usize,
+    /// Fill uniform rectangles instead of computing every pixel of the last pass.
+    pub fill: bool,
+}
 
 pub struct Job {
-    pbo: &'static mut[u8],
-    row: usize,
-    width: usize,
-    heigth: usize,
-    scale: (f8_120, f8_120),
-    center: (f8_120, f8_120),
+    settings: RenderSettings,
+    tile: Tile,
 }
 
 impl Job {
-    pub fn new(pbo: &'static mut[u8], row: usize, width: usize, heigth: usize, scale: (f8_120, f8_120), center: (f8_120, f8_120)) -> Job{
+    pub fn new(settings: &RenderSettings, tile: Tile) -> Job{
         Job{
-            pbo: pbo,
-            row: row,
+            settings: settings.clone(),
+            tile: tile,
+        }
+    }
+}
+
+pub struct DensityJob {
+    density: Arc<Mutex<Density>>,
+    width: usize,
+    heigth: usize,
+    scale: (f64, f64),
+    center: (f64, f64),
+    fractal: Arc<Fractal>,
+    seed: u32,
+    /// `Progress::generation` at the time the job was pushed, orbits of an abandoned view are
+    /// thrown away.
+    generation: usize,
+}
+
+impl DensityJob {
+    pub fn new(density: Arc<Mutex<Density>>, width: usize, heigth: usize, scale: (f8_120, f8_120), center: (f8_120, f8_120), fractal: Arc<Fractal>, seed: u32, generation: usize) -> DensityJob{
+        DensityJob{
+            density: density,
             width: width,
             heigth: heigth,
-            scale: scale,
-            center: center,
+            scale: (f64::from(scale.0), f64::from(scale.1)),
+            center: (f64::from(center.0), f64::from(center.1)),
+            fractal: fractal,
+            seed: seed,
+            generation: generation,
+        }
+    }
+}
+
+/// Everything the `Colorize` jobs of a frame have in common, only their bands differ.
+#[derive(Clone)]
+pub struct ColorSettings {
+    /// Samples of the whole view, blocks can reach into rows of the previous job.
+    pub samples: &'static [Sample],
+    /// Subsamples of the refined pixels of the view, the same number for every pixel.
+    pub subsamples: &'static [Sample],
+    pub width: usize,
+    /// Block size of the last finished pass.
+    pub block: usize,
+    pub fractal: Arc<Fractal>,
+    pub coloring: Arc<Coloring>,
+    pub histogram: Option<Arc<Histogram>>,
+    pub gradient: Arc<Gradient>,
+    /// Composited over the colouring, bottom first.
+    pub layers: Arc<Vec<Layer>>,
+    pub dither: Arc<Dither>,
+    /// How layers are composited and subsamples averaged.
+    pub gamma: Gamma,
+    /// Colour cycling offset, see `interpolate`.
+    pub phase: f64,
+}
+
+pub struct ColorJob {
+    settings: ColorSettings,
+    /// Pixels of the rows from `row` on.
+    target: Target<'static>,
+    row: usize,
+}
+
+impl ColorJob {
+    pub fn new(settings: &ColorSettings, target: Target<'static>, row: usize) -> ColorJob{
+        ColorJob{
+            settings: settings.clone(),
+            target: target,
+            row: row,
+        }
+    }
+}
+
+/// Shared between the main thread and the workers to cancel `Render` jobs.
+pub struct Progress {
+    /// Bumped by the main thread to abandon all render jobs pushed before.
+    pub generation: AtomicUsize,
+}
+
+impl Progress {
+    pub fn new() -> Progress {
+        Progress{
+            generation: AtomicUsize::new(0),
         }
     }
 }
 
 pub struct Worker {
-    barrier: Arc<Barrier>,
+    progress: Arc<Progress>,
 }
 
 impl Worker {
-    pub fn new( barrier: Arc<Barrier>) -> Worker {
+    pub fn new( progress: Arc<Progress>) -> Worker {
         Worker{
-            barrier: barrier,
+            progress: progress,
+        }
+    }
+
+    fn cancelled(&self, generation: usize) -> bool {
+        self.progress.generation.load(Ordering::Relaxed) != generation
+    }
+
+    pub fn run( &mut self, task: Task ){
+        match task {
+            Task::Render(job) => self.do_job(job),
+            Task::Refine(job) => self.do_refine_job(job),
+            Task::Accumulate(job) => self.do_density_job(job),
+            Task::Colorize(job) => self.do_color_job(job),
+        }
+    }
+
+    fn do_job( &mut self, job: Job) {
+        let (width, tile) = (job.settings.width, job.tile);
+        let (step_x, step_y) = Self::steps(&job);
+        let grid = Self::grid_offsets(job.settings.antialiasing, step_x, step_y);
+        let mut rng = XorShiftRng::from_seed([(tile.y * width + tile.x) as u32 + 1, 0x193a6754, 0xa8a7d469, 0x97830e05]);
+
+        let sample_at = |x: f8_120, y: f8_120| Self::sample_at(&job, x, y);
+        let samples = job.settings.samples;
+        let per_pixel = samples.per_pixel;
+        let tile_row = |line: usize| unsafe { samples.slice((tile.y + line) * width + tile.x, tile.width) };
+
+        // Subsamples are only taken in the last pass, coarse passes are meant to be quick.
+        let last = job.settings.pass.map_or(true, |pass| pass + 1 == PASSES.len());
+        let (rows, cols) = job.settings.kept;
+        let whole_tile = rows.1 <= tile.y || rows.0 >= tile.y + tile.heigth || cols.1 <= tile.x || cols.0 >= tile.x + tile.width;
+        // Rectangles are only filled with plain samples, subsampled modes need every pixel.
+        let rectangles = job.settings.fill && last && whole_tile && match job.settings.antialiasing {
+            Antialiasing::Off | Antialiasing::Adaptive(_) => true,
+            Antialiasing::Grid(_) | Antialiasing::Jittered(_) => false,
+        };
+        if rectangles {
+            self.fill_rectangles(&job, &|col: usize, row: usize| {
+                let (x, y) = Self::view_point(&job, col, row);
+                sample_at(x, y)
+            });
+        }else{
+            for line in 0 .. tile.heigth {
+                if self.cancelled(job.settings.generation) {
+                    return;
+                }
+                let row = tile.y + line;
+                if row >= rows.0 && row < rows.1 && cols.0 <= tile.x && cols.1 >= tile.x + tile.width {
+                    continue;
+                }
+                let (mut x, y) = Self::view_point(&job, tile.x, row);
+                let (pixels, subsamples) = tile_row(line);
+                for (n, pixel) in pixels.iter_mut().enumerate() {
+                    let col = tile.x + n;
+                    if !exposed(job.settings.kept, col, row) {
+                        x = x + step_x;
+                        continue;
+                    }
+                    let cell = &mut subsamples[n * per_pixel .. (n + 1) * per_pixel];
+                    match job.settings.antialiasing {
+                        Antialiasing::Grid(_) if last => {
+                            for (subsample, &(dx, dy)) in cell.iter_mut().zip(grid.iter()) {
+                                *subsample = sample_at(x + dx, y + dy);
+                            }
+                            *pixel = cell[0];
+                            cell[0].flags |= REFINED;
+                        },
+                        Antialiasing::Jittered(size) if last => {
+                            for (k, subsample) in cell.iter_mut().enumerate() {
+                                let dx = ((k % size) as f64 + rng.gen::<f64>()) / size as f64 - 0.5;
+                                let dy = ((k / size) as f64 + rng.gen::<f64>()) / size as f64 - 0.5;
+                                *subsample = sample_at(x + f8_120::from(dx) * step_x, y + f8_120::from(dy) * step_y);
+                            }
+                            *pixel = cell[0];
+                            cell[0].flags |= REFINED;
+                        },
+                        _ => if job.settings.pass.map_or(true, |pass| computed_in(pass, col, row)) {
+                            *pixel = sample_at(x, y);
+                            if let Some(first) = cell.first_mut() {
+                                first.flags &= !REFINED;
+                            }
+                        },
+                    }
+                    x = x + step_x;
+                }
+            }
         }
     }
 
-    pub fn run( &mut self, jobs: Arc<Mutex<Vec<Job>>> ){
-        loop{
-            let job = jobs.lock().unwrap().pop();
-            match job {
-                Some(job) => self.do_job(job),
-                None => {
-                    self.barrier.wait();
-                    self.barrier.wait();
+    /// Subsamples the pixels of the job's tile that differ from a neighbour, once the `Render`
+    /// jobs of the pass are all finished. Neighbours in other tiles count as well, so there
+    /// are no seams along the tile edges. Only the samples are read and only the subsamples of
+    /// the tile written, refine jobs can run side by side.
+    fn do_refine_job( &mut self, job: Job) {
+        let (width, heigth, tile) = (job.settings.width, job.settings.heigth, job.tile);
+        let (step_x, step_y) = Self::steps(&job);
+        let grid = Self::grid_offsets(job.settings.antialiasing, step_x, step_y);
+        let samples = job.settings.samples;
+        let at = |col: usize, row: usize| unsafe { samples.at(row * width + col) };
+
+        for row in tile.y .. tile.y + tile.heigth {
+            if self.cancelled(job.settings.generation) {
+                return;
+            }
+            for col in tile.x .. tile.x + tile.width {
+                if !exposed(job.settings.kept, col, row) {
+                    continue;
+                }
+                let sample = at(col, row);
+                let refine = (col > 0 && sample.differs(&at(col - 1, row))) ||
+                    (col + 1 < width && sample.differs(&at(col + 1, row))) ||
+                    (row > 0 && sample.differs(&at(col, row - 1))) ||
+                    (row + 1 < heigth && sample.differs(&at(col, row + 1)));
+                if !refine {
                     continue;
                 }
-            };
+                let (x, y) = Self::view_point(&job, col, row);
+                let cell = unsafe { samples.subsamples(row * width + col) };
+                for (subsample, &(dx, dy)) in cell.iter_mut().zip(grid.iter()) {
+                    *subsample = Self::sample_at(&job, x + dx, y + dy);
+                }
+                cell[0].flags |= REFINED;
+            }
         }
     }
 
-    fn do_job( &mut self, job: Job) {
-        use std::num::Zero;
-        let offset = now().tm_nsec as f32 / (1_000_000_000.0) * PI * 2.0;
-        let mut x = -_1;
-        let mut y = f8_120::from(((job.row as f64/job.heigth as f64) - 0.5) * 2.0);
-        let step_x = f8_120::from(2.0/job.width as f64);
-        let step_y = f8_120::from(2.0/job.heigth as f64);
-        let max = 100;
-
-        for row in job.pbo.chunks_mut(4*job.width) {
-            for pixel in row.chunks_mut(4) {
-                {
-                    let x = -job.center.0 + x * job.scale.0;
-                    let y = -job.center.1 + y * job.scale.1;
-                    
-                    let i = Self::escape_time(x, y, max);
-
-                    if i as i32 == max {
-                        pixel[0] = 0;
-                        pixel[1] = 0;
-                        pixel[2] = 0;
-                        pixel[3] = 255;
-                    }else{
-                        let color = interpolate(&TEXTURE, i);
-                        pixel[0] = color.0;
-                        pixel[1] = color.1;
-                        pixel[2] = color.2;
-                        pixel[3] = 255;
+    /// Size of a pixel in view coordinates.
+    fn steps(job: &Job) -> (f8_120, f8_120) {
+        (f8_120::from(2.0 / job.settings.width as f64), f8_120::from(2.0 / job.settings.heigth as f64))
+    }
+
+    /// View coordinates, -1 to 1 across the view, of pixel `col`, `row`.
+    fn view_point(job: &Job, col: usize, row: usize) -> (f8_120, f8_120) {
+        (f8_120::from(col as f64 * 2.0 / job.settings.width as f64 - 1.0), f8_120::from(((row as f64 / job.settings.heigth as f64) - 0.5) * 2.0))
+    }
+
+    /// Computes the sample at view coordinates `x`, `y`.
+    fn sample_at(job: &Job, x: f8_120, y: f8_120) -> Sample {
+        let x = -job.settings.center.0 + x * job.settings.scale.0;
+        let y = -job.settings.center.1 + y * job.settings.scale.1;
+
+        match *job.settings.fractal {
+            // Nothing reads the distance estimate any more, it is left out.
+            Fractal::Mandelbrot => Self::mandelbrot_sample(x, y, MAX_ITERATIONS, &job.settings.coloring, false),
+            Fractal::Newton(ref newton) => Self::newton_sample(newton, Complex::new(f64::from(x), f64::from(y))),
+            Fractal::Lyapunov(ref lyapunov) => Sample{
+                stat: lyapunov.exponent(f64::from(x), f64::from(y)),
+                ..Sample::default()
+            },
+            Fractal::Buddhabrot(_) => unreachable!("Density fractals are rendered with DensityJob"),
+        }
+    }
+
+    /// Mariani-Silver fill of the job's tile: computes the border of a rectangle and fills the
+    /// inside with the border when all of it lies in the same region (see `Sample::same_region`),
+    /// otherwise splits it in four and carries on with those. Features smaller than a rectangle that do not touch its
+    /// border are lost.
+    fn fill_rectangles<F: Fn(usize, usize) -> Sample>(&self, job: &Job, compute: &F) {
+        let (tile, width) = (job.tile, job.settings.width);
+        let index = |x: usize, y: usize| (tile.y + y) * width + tile.x + x;
+        let mut done = vec![false; tile.width * tile.heigth];
+        let mut stack = vec![(0, 0, tile.width - 1, tile.heigth - 1)];
+
+        while let Some((x0, y0, x1, y1)) = stack.pop() {
+            if self.cancelled(job.settings.generation) {
+                return;
+            }
+
+            let mut border = Vec::new();
+            for x in x0 .. x1 + 1 {
+                border.push((x, y0));
+                border.push((x, y1));
+            }
+            for y in y0 + 1 .. y1 {
+                border.push((x0, y));
+                border.push((x1, y));
+            }
+            for &(x, y) in &border {
+                if !done[y * tile.width + x] {
+                    unsafe { job.settings.samples.set(index(x, y), compute(tile.x + x, tile.y + y)) };
+                    done[y * tile.width + x] = true;
+                }
+            }
+            if x1 - x0 < 2 || y1 - y0 < 2 {
+                continue;
+            }
+
+            let first = unsafe { job.settings.samples.at(index(x0, y0)) };
+            if border.iter().all(|&(x, y)| unsafe { job.settings.samples.at(index(x, y)) }.same_region(&first)) {
+                for y in y0 + 1 .. y1 {
+                    for x in x0 + 1 .. x1 {
+                        unsafe { job.settings.samples.set(index(x, y), first) };
+                        done[y * tile.width + x] = true;
                     }
                 }
-                x = x + step_x;
+            }else{
+                let (mx, my) = ((x0 + x1) / 2, (y0 + y1) / 2);
+                stack.push((x0, y0, mx, my));
+                stack.push((mx, y0, x1, my));
+                stack.push((x0, my, mx, y1));
+                stack.push((mx, my, x1, y1));
             }
-            x = -_1;
-            y = y + step_y;
         }
     }
-    
-    fn partial_et<T>(x: T, y: T, max_i: i32, max_xy: T, two: T, mut x1: T, mut y1: T, mut i: i32) -> (T, T, i32)
-        where T: PartialOrd + Add<Output=T> + Sub<Output=T> + Mul<Output=T> + Copy{
+
+    /// Offsets of the cell centers of an n x n subsample grid, in view coordinates.
+    fn grid_offsets(antialiasing: Antialiasing, step_x: f8_120, step_y: f8_120) -> Vec<(f8_120, f8_120)> {
+        let n = match antialiasing {
+            Antialiasing::Off => 1,
+            Antialiasing::Grid(n) | Antialiasing::Jittered(n) | Antialiasing::Adaptive(n) => n,
+        };
+        (0 .. n * n).map(|cell| {
+            let dx = ((cell % n) as f64 + 0.5) / n as f64 - 0.5;
+            let dy = ((cell / n) as f64 + 0.5) / n as f64 - 0.5;
+            (f8_120::from(dx) * step_x, f8_120::from(dy) * step_y)
+        }).collect()
+    }
+
+    fn do_color_job( &mut self, mut job: ColorJob) {
+        let settings = &job.settings;
+        let histogram = settings.histogram.as_ref().map(|histogram| &**histogram);
+        let (width, block) = (settings.width, settings.block);
+        let per_pixel = settings.subsamples.len() / settings.samples.len();
+        let (coloring, fractal, gradient, layers, gamma, phase) = (&settings.coloring, &settings.fractal, &settings.gradient, &settings.layers, settings.gamma, settings.phase);
+        let color = |sample: &Sample| compose(layers, coloring.color(fractal, sample, histogram, gradient, phase), sample, phase, gamma);
+        for n in 0 .. job.target.pixels() {
+            // Pixels the passes so far skipped take the sample in the corner of their block.
+            let (col, row) = (n % width, job.row + n / width);
+            let first = (row - row % block) * width + col - col % block;
+            let samples = &settings.subsamples[first * per_pixel .. (first + 1) * per_pixel];
+            if samples.first().map_or(true, |sample| sample.flags & REFINED == 0) {
+                job.target.put(n, color(&settings.samples[first]), col, row, &settings.dither);
+                continue;
+            }
+
+            // Average the subsamples in linear light, unless asked not to, averaging sRGB values
+            // darkens edges.
+            let mut sum = (0.0, 0.0, 0.0);
+            for sample in samples {
+                let color = gamma.decode(color(sample).unwrap_or((0.0, 0.0, 0.0)));
+                sum = (sum.0 + color.0, sum.1 + color.1, sum.2 + color.2);
+            }
+            let count = samples.len() as f64;
+            let color = gamma.encode((sum.0 / count, sum.1 / count, sum.2 / count));
+            job.target.put(n, Some(color), col, row, &settings.dither);
+        }
+    }
+
+    fn do_density_job( &mut self, job: DensityJob) {
+        let buddhabrot = match *job.fractal {
+            Fractal::Buddhabrot(ref buddhabrot) => buddhabrot,
+            _ => unreachable!("DensityJob needs a density fractal"),
+        };
+
+        let mut rng = XorShiftRng::from_seed([job.seed, 0x193a6754, 0xa8a7d469, 0x97830e05]);
+        let mut counts = vec![0u32; job.width * job.heigth * 3];
+        let mut left = buddhabrot.samples;
+        while left > 0 {
+            if self.cancelled(job.generation) {
+                return;
+            }
+            let samples = cmp::min(left, DENSITY_CHUNK);
+            buddhabrot.accumulate(&mut rng, samples, &mut counts, job.width, job.heigth, job.scale, job.center);
+            left -= samples;
+        }
+        job.density.lock().unwrap().add(&counts);
+    }
+
+    fn partial_et<T, O>(x: T, y: T, max_i: i32, max_xy: T, two: T, mut x1: T, mut y1: T, mut i: i32, observer: &mut O) -> (T, T, i32)
+        where T: PartialOrd + Add<Output=T> + Sub<Output=T> + Mul<Output=T> + Copy + Into<f64>, O: OrbitObserver{
         let mut xx = x1 * x1;
         let mut yy = y1 * y1;
         while xx + yy <= max_xy && i < max_i {
@@ -100,16 +487,20 @@ impl Worker {
             y1 = two*x1*y1 + y;
             x1 = xtemp;
             i += 1;
+            if observer.active() {
+                observer.visit(Complex::new(x1.into(), y1.into()));
+            }
             xx = x1 * x1;
             yy = y1 * y1;
         }
         (x1, y1, i)
     }
     
-    fn escape_time(x: f8_120, y: f8_120, max: i32) -> f64{
+    /// Returns the smooth iteration count and the last value of the orbit.
+    fn escape_time<O: OrbitObserver>(x: f8_120, y: f8_120, max: i32, observer: &mut O) -> (f64, Complex){
         
-        let (x1, y1, i) = Self::partial_et(x, y, max, _1, _2, x, y, 0);
-        let (x1, y1, i) = Self::partial_et(f64::from(x), f64::from(y), max, 256.0 * 256.0, 2.0, f64::from(x1), f64::from(y1), i);
+        let (x1, y1, i) = Self::partial_et(x, y, max, _1, _2, x, y, 0, observer);
+        let (x1, y1, i) = Self::partial_et(f64::from(x), f64::from(y), max, BAILOUT * BAILOUT, 2.0, f64::from(x1), f64::from(y1), i, observer);
         
         let log_zn = (x1 * x1 + y1 * y1).ln() / 2.0;
         let nu = (log_zn / 2.0f64.ln()).ln() / 2.0f64.ln();
@@ -117,27 +508,80 @@ impl Worker {
         
         //i as f32
         if i == max {
-            max as f64
+            (max as f64, Complex::new(x1, y1))
         }else{
-            col
+            (col, Complex::new(x1, y1))
+        }
+    }
+
+    /// Runs `escape_time` with the observers `coloring` needs, and the distance estimate if
+    /// `distance` asks for it. Plain colourings observe nothing, the orbit is not even converted
+    /// to floating point for them.
+    fn mandelbrot_sample(x: f8_120, y: f8_120, max: i32, coloring: &Coloring, distance: bool) -> Sample {
+        if distance {
+            let (mut sample, observer) = Self::observed_sample(x, y, max, coloring, DistanceObserver::new(Complex::new(f64::from(x), f64::from(y))));
+            sample.de = observer.estimate();
+            sample
+        }else{
+            Self::observed_sample(x, y, max, coloring, ()).0
         }
     }
-}
 
-fn interpolate(tex: &[u8], color: f64) -> (u8, u8, u8){
-    let fract = color.fract();
-    let trunc = color.trunc() as usize;
-    let n1 = trunc%(tex.len()/3)*3;
-    let n2 = (trunc + 1)%(tex.len()/3)*3;
-    let col1 = &tex[n1..n1+3];
-    let col2 = &tex[n2..n2+3];
-    (
-        (col2[0] as f64 * fract + col1[0] as f64 * (1.0-fract)) as u8,
-        (col2[1] as f64 * fract + col1[1] as f64 * (1.0-fract)) as u8,
-        (col2[2] as f64 * fract + col1[2] as f64 * (1.0-fract)) as u8,
-    )
+    /// `mandelbrot_sample` with `extra` observing the orbit along with the colouring's observers.
+    fn observed_sample<O: OrbitObserver>(x: f8_120, y: f8_120, max: i32, coloring: &Coloring, extra: O) -> (Sample, O) {
+        let c = Complex::new(f64::from(x), f64::from(y));
+        let mut sample = Sample::default();
+        let (i, z, extra) = match *coloring {
+            Coloring::OrbitTrap(ref trap) => {
+                let mut observer = (extra, TrapObserver::new(trap));
+                let (i, z) = Self::escape_time(x, y, max, &mut observer);
+                sample.stat = observer.1.distance;
+                sample.hit = observer.1.hit_color();
+                (i, z, observer.0)
+            },
+            Coloring::Average(ref statistic) => {
+                let mut observer = (extra, AverageObserver::new(statistic, c));
+                let (i, z) = Self::escape_time(x, y, max, &mut observer);
+                sample.stat = observer.1.value(BAILOUT).unwrap_or(::std::f64::NAN);
+                (i, z, observer.0)
+            },
+            _ => {
+                let mut observer = extra;
+                let (i, z) = Self::escape_time(x, y, max, &mut observer);
+                (i, z, observer)
+            },
+        };
+        sample.iter = i;
+        sample.z = z;
+        if i as i32 == max {
+            sample.flags |= INSIDE;
+        }
+        (sample, extra)
+    }
+
+    fn newton_sample(newton: &Newton, z: Complex) -> Sample {
+        match newton.solve(z) {
+            Some((root, i)) => Sample{
+                iter: i,
+                stat: root as f64,
+                ..Sample::default()
+            },
+            None => Sample{
+                flags: INSIDE,
+                ..Sample::default()
+            },
+        }
+    }
 }
 
+pub const MAX_ITERATIONS: i32 = 100;
+
+/// Orbits a `DensityJob` samples between checks whether it got cancelled.
+const DENSITY_CHUNK: usize = 1000;
+
+/// Escape radius of the second, floating point, stage of `escape_time`.
+const BAILOUT: f64 = 256.0;
+
 const _0: f8_120 = f8_120{ words: (0b00000000_00000000000000000000000000000000000000000000000000000000, 0), sign: Sign::Neutral};
 const _1: f8_120 = f8_120{ words: (0b00000001_00000000000000000000000000000000000000000000000000000000, 0), sign: Sign::Positive};
 const _2: f8_120 = f8_120{ words: (0b00000010_00000000000000000000000000000000000000000000000000000000, 0), sign: Sign::Positive};